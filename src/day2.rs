@@ -1,10 +1,10 @@
+use anyhow::anyhow;
 use itertools::Itertools;
+use std::fmt;
 use std::fs::File;
 use std::io::BufRead;
 use std::path::PathBuf;
 
-const MAX_DIFF: i8 = 3;
-
 fn read_numbers(input: &PathBuf) -> anyhow::Result<Vec<Vec<i8>>> {
     let file = File::open(input)?;
     let reader = std::io::BufReader::new(file);
@@ -21,32 +21,196 @@ fn read_numbers(input: &PathBuf) -> anyhow::Result<Vec<Vec<i8>>> {
     Ok(list)
 }
 
-fn validate_report(report: &[i8]) -> bool {
-    let is_ascending = report.is_sorted_by(|a, b| a < b);
-    let is_descending = report.is_sorted_by(|a, b| a > b);
-    let in_bounds = report
-        .iter()
-        .tuple_windows()
-        .all(|(a, b)| (a - b).abs() <= MAX_DIFF);
-    (is_ascending || is_descending) && in_bounds
+#[derive(Copy, Clone)]
+struct Dampener {
+    max_removals: usize,
+    min_step: i8,
+    max_diff: i8,
+}
+
+impl Dampener {
+    fn new(max_removals: usize, min_step: i8, max_diff: i8) -> anyhow::Result<Self> {
+        if min_step < 1 || min_step > max_diff {
+            return Err(anyhow!(
+                "Step bounds must satisfy 1 <= min step <= max diff, got {} and {}",
+                min_step,
+                max_diff
+            ));
+        }
+        Ok(Self {
+            max_removals,
+            min_step,
+            max_diff,
+        })
+    }
+
+    fn valid_step(&self, a: i8, b: i8, ascending: bool) -> bool {
+        let step = if ascending {
+            b as i16 - a as i16
+        } else {
+            a as i16 - b as i16
+        };
+        step >= self.min_step as i16 && step <= self.max_diff as i16
+    }
+
+    // Minimal set of removed indices that makes the report monotone in the
+    // given direction, found by a DP over the last kept level. Since at most
+    // `max_removals` levels can be skipped, each level only looks back that far.
+    fn removals_for_direction(&self, report: &[i8], ascending: bool) -> Option<Vec<usize>> {
+        let n = report.len();
+        let k = self.max_removals;
+        // best[i] is the fewest removals for a valid prefix ending by keeping i
+        let mut best: Vec<Option<usize>> = vec![None; n];
+        let mut parent: Vec<Option<usize>> = vec![None; n];
+
+        for i in 0..n {
+            if i <= k {
+                best[i] = Some(i);
+            }
+            for p in i.saturating_sub(k + 1)..i {
+                let Some(removed) = best[p] else {
+                    continue;
+                };
+                let removed = removed + (i - p - 1);
+                if removed > k || !self.valid_step(report[p], report[i], ascending) {
+                    continue;
+                }
+                if best[i].is_none_or(|b| removed < b) {
+                    best[i] = Some(removed);
+                    parent[i] = Some(p);
+                }
+            }
+        }
+
+        let last = (n.saturating_sub(k + 1)..n)
+            .filter_map(|i| best[i].map(|b| (b + (n - 1 - i), i)))
+            .filter(|(removed, _)| *removed <= k)
+            .min()
+            .map(|(_, i)| i);
+
+        let Some(last) = last else {
+            // Only an empty report is left when every level may be removed
+            return (n <= k).then(|| (0..n).collect());
+        };
+
+        let mut kept = vec![false; n];
+        let mut curr = Some(last);
+        while let Some(i) = curr {
+            kept[i] = true;
+            curr = parent[i];
+        }
+        Some((0..n).filter(|&i| !kept[i]).collect())
+    }
+
+    fn removals(&self, report: &[i8]) -> Option<Vec<usize>> {
+        [true, false]
+            .into_iter()
+            .filter_map(|ascending| self.removals_for_direction(report, ascending))
+            .min_by_key(|removed| removed.len())
+    }
+
+    fn is_safe(&self, report: &[i8]) -> bool {
+        self.removals(report).is_some()
+    }
+
+    fn first_violation(&self, report: &[i8]) -> Option<Violation> {
+        let mut ascending = None;
+        for (j, (&a, &b)) in report.iter().tuple_windows().enumerate() {
+            let step = b as i16 - a as i16;
+            if step != 0 && *ascending.get_or_insert(step > 0) != (step > 0) {
+                return Some(Violation::DirectionChange(j));
+            }
+            // A flat step reads the same either way round
+            if !self.valid_step(a, b, ascending.unwrap_or(true)) {
+                return Some(match step {
+                    0 => Violation::Flat(j),
+                    _ => Violation::Step(j, step.abs()),
+                });
+            }
+        }
+        None
+    }
+
+    fn classify(&self, report: &[i8]) -> Verdict {
+        match self.first_violation(report) {
+            None => Verdict::Safe,
+            Some(violation) => match self.removals(report) {
+                Some(removed) => Verdict::Dampened(removed),
+                None => Verdict::Unsafe(violation),
+            },
+        }
+    }
+}
+
+enum Violation {
+    Flat(usize),
+    DirectionChange(usize),
+    Step(usize, i16),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Flat(j) => write!(f, "no change between levels {} and {}", j, j + 1),
+            Violation::DirectionChange(j) => write!(f, "direction change at position {}", j),
+            Violation::Step(j, step) => {
+                write!(f, "step of {} between levels {} and {}", step, j, j + 1)
+            }
+        }
+    }
+}
+
+enum Verdict {
+    Safe,
+    Dampened(Vec<usize>),
+    Unsafe(Violation),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Safe => write!(f, "safe,"),
+            Verdict::Dampened(removed) => {
+                write!(f, "safe after removing index {},", removed.iter().join(" "))
+            }
+            Verdict::Unsafe(violation) => write!(f, "unsafe,{}", violation),
+        }
+    }
 }
 
-fn validate_dampend_report(report: &[i8]) -> bool {
-    (0..report.len()).any(|i| {
-        let (left, right) = report.split_at(i);
-        validate_report(&[left, &right[1..]].concat())
-    })
+pub fn part_a(input: &PathBuf, min_step: i8, max_diff: i8) -> anyhow::Result<i32> {
+    let list = read_numbers(input)?;
+    let dampener = Dampener::new(0, min_step, max_diff)?;
+    Ok(list.into_iter().filter(|x| dampener.is_safe(x)).count() as i32)
 }
 
-pub fn part_a(input: &PathBuf) -> anyhow::Result<i32> {
+pub fn part_b(
+    input: &PathBuf,
+    max_removals: usize,
+    min_step: i8,
+    max_diff: i8,
+) -> anyhow::Result<i32> {
     let list = read_numbers(input)?;
-    Ok(list.into_iter().filter(|x| validate_report(x)).count() as i32)
+    let dampener = Dampener::new(max_removals, min_step, max_diff)?;
+    Ok(list.into_iter().filter(|x| dampener.is_safe(x)).count() as i32)
 }
 
-pub fn part_b(input: &PathBuf) -> anyhow::Result<i32> {
+pub fn explain(
+    input: &PathBuf,
+    max_removals: usize,
+    min_step: i8,
+    max_diff: i8,
+) -> anyhow::Result<String> {
     let list = read_numbers(input)?;
-    Ok(list
-        .into_iter()
-        .filter(|x| validate_dampend_report(x))
-        .count() as i32)
+    let dampener = Dampener::new(max_removals, min_step, max_diff)?;
+    let mut out = String::from("line,report,status,reason\n");
+    for (i, report) in list.iter().enumerate() {
+        out.push_str(&format!(
+            "{},{},{}\n",
+            i + 1,
+            report.iter().join(" "),
+            dampener.classify(report)
+        ));
+    }
+    Ok(out)
 }
@@ -5,7 +5,7 @@ fn read_to_string(input: &Path) -> anyhow::Result<String> {
     std::fs::read_to_string(input).map_err(Into::into)
 }
 
-fn count_col(v: &Vec<Vec<char>>, i: usize) -> usize {
+fn count_col(v: &[Vec<char>], i: usize) -> usize {
     v.iter().map(|l| l[i]).filter(|c| c == &'#').count()
 }
 
@@ -36,7 +36,7 @@ fn parse(s: &str) -> (Vec<[usize; 5]>, Vec<[usize; 5]>) {
 }
 
 fn check(key: &[usize; 5], lock: &[usize; 5]) -> bool {
-    let mut comp = key.into_iter().zip_eq(lock).map(|(k, l)| k + l);
+    let mut comp = key.iter().zip_eq(lock).map(|(k, l)| k + l);
     comp.all(|x| x <= 5)
 }
 
@@ -48,7 +48,7 @@ pub fn part_a(input: &Path) -> anyhow::Result<usize> {
     let mut count = 0;
     for k in keys {
         for l in &locks {
-            if check(&k, &l) {
+            if check(&k, l) {
                 count += 1
             }
         }
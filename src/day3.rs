@@ -1,37 +1,566 @@
-use regex::Regex;
+use anyhow::anyhow;
+use itertools::Itertools;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Bytes, Read};
+use std::ops::Range;
 use std::path::Path;
+use std::str::FromStr;
 
-fn read_to_string(input: &Path) -> anyhow::Result<String> {
-    std::fs::read_to_string(input).map_err(Into::into)
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
 }
 
-fn find_mul_strings(contents: &str) -> anyhow::Result<Vec<(u16, u16)>> {
-    let re = Regex::new(r"mul\((\d{1,3}),(\d{1,3})\)").unwrap();
-    Ok(re
-        .captures_iter(contents)
-        .map(|x| {
-            let (_, [a, b]) = x.extract();
-            (a.parse::<u16>().unwrap(), b.parse::<u16>().unwrap())
+#[derive(Clone, Debug)]
+struct Instruction {
+    name: String,
+    op: Op,
+    arity: usize,
+    max_digits: usize,
+    width: u32,
+}
+
+impl Instruction {
+    fn new(op: Op) -> Self {
+        let name = match op {
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Mul => "mul",
+        };
+        Self {
+            name: name.to_string(),
+            op,
+            arity: 2,
+            max_digits: 3,
+            width: 64,
+        }
+    }
+
+    // Operands are folded left to right and the result wraps to `width` bits
+    fn evaluate(&self, operands: &[i64]) -> i64 {
+        let value = operands
+            .iter()
+            .map(|&x| x as i128)
+            .reduce(|acc, x| match self.op {
+                Op::Add => acc.wrapping_add(x),
+                Op::Sub => acc.wrapping_sub(x),
+                Op::Mul => acc.wrapping_mul(x),
+            })
+            .unwrap_or(0);
+        let shift = 128 - self.width;
+        ((value << shift) >> shift) as i64
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = anyhow::Error;
+
+    // Parses `name[:arity[:digits[:width]]]`, e.g. `add:3:4:32`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut fields = s.trim().split(':');
+        let op = match fields.next() {
+            Some("add") => Op::Add,
+            Some("sub") => Op::Sub,
+            Some("mul") => Op::Mul,
+            other => return Err(anyhow!("Unknown instruction {:?}", other)),
+        };
+        let mut instruction = Instruction::new(op);
+        if let Some(arity) = fields.next() {
+            instruction.arity = arity.parse()?;
+        }
+        if let Some(max_digits) = fields.next() {
+            instruction.max_digits = max_digits.parse()?;
+        }
+        if let Some(width) = fields.next() {
+            instruction.width = width.parse()?;
+        }
+        if fields.next().is_some() {
+            return Err(anyhow!("Too many fields in {:?}", s));
+        }
+        if instruction.arity == 0 {
+            return Err(anyhow!("{} needs at least one operand", instruction.name));
+        }
+        if !(1..=18).contains(&instruction.max_digits) {
+            return Err(anyhow!("Operands must have 1 to 18 digits"));
+        }
+        if !(1..=64).contains(&instruction.width) {
+            return Err(anyhow!("Result width must be 1 to 64 bits"));
+        }
+        Ok(instruction)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InstructionSet {
+    instructions: Vec<Instruction>,
+    // The `name(` each instruction starts with, built once since every byte
+    // of the input is scanned against them
+    openings: Vec<Vec<u8>>,
+}
+
+impl FromStr for InstructionSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let instructions: Vec<Instruction> = s.split(',').map(str::parse).try_collect()?;
+        if let Some(name) = instructions.iter().map(|i| &i.name).duplicates().next() {
+            return Err(anyhow!("{} is defined more than once", name));
+        }
+        let openings = instructions
+            .iter()
+            .map(|i| i.name.bytes().chain([b'(']).collect())
+            .collect();
+        Ok(Self {
+            instructions,
+            openings,
         })
-        .collect())
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Instruction(usize, Vec<i64>),
+    Do,
+    Dont,
+}
+
+enum Scan {
+    Incomplete,
+    Complete(Token),
+    Invalid,
+}
+
+// Only counts operands and digits until the closing parenthesis, so nothing
+// is allocated for the many candidates that turn out invalid
+fn scan_operands(instruction: &Instruction, rest: &[u8]) -> Scan {
+    let (mut count, mut digits) = (1, 0);
+    for (i, &c) in rest.iter().enumerate() {
+        match c {
+            b'0'..=b'9' if digits < instruction.max_digits => digits += 1,
+            b',' if count < instruction.arity && digits > 0 => {
+                count += 1;
+                digits = 0;
+            }
+            b')' if count == instruction.arity && digits > 0 => {
+                let operands = rest[..i]
+                    .split(|&c| c == b',')
+                    .map(|n| n.iter().fold(0, |x, d| x * 10 + (d - b'0') as i64))
+                    .collect();
+                return Scan::Complete(Token::Instruction(0, operands));
+            }
+            _ => return Scan::Invalid,
+        }
+    }
+    Scan::Incomplete
+}
+
+impl InstructionSet {
+    fn scan(&self, buf: &[u8]) -> Scan {
+        for (literal, token) in [(&b"do()"[..], Token::Do), (&b"don't()"[..], Token::Dont)] {
+            if buf == literal {
+                return Scan::Complete(token);
+            }
+        }
+        let mut incomplete = [&b"do()"[..], b"don't()"]
+            .iter()
+            .any(|literal| literal.starts_with(buf));
+
+        for (index, (instruction, opening)) in
+            self.instructions.iter().zip(&self.openings).enumerate()
+        {
+            if let Some(rest) = buf.strip_prefix(opening.as_slice()) {
+                match scan_operands(instruction, rest) {
+                    Scan::Complete(Token::Instruction(_, operands)) => {
+                        return Scan::Complete(Token::Instruction(index, operands));
+                    }
+                    Scan::Incomplete => incomplete = true,
+                    _ => (),
+                }
+            } else if opening.starts_with(buf) {
+                incomplete = true;
+            }
+        }
+
+        if incomplete {
+            Scan::Incomplete
+        } else {
+            Scan::Invalid
+        }
+    }
+}
+
+struct Lexeme {
+    token: Token,
+    span: Range<usize>,
+}
+
+// Reads one byte at a time so the input never has to fit in memory. Bytes of
+// a failed candidate token are replayed, since a real token may start inside it.
+struct Lexer<'a, R: Read> {
+    set: &'a InstructionSet,
+    bytes: Bytes<BufReader<R>>,
+    replay: VecDeque<u8>,
+    buf: Vec<u8>,
+    start: usize,
+}
+
+impl<'a, R: Read> Lexer<'a, R> {
+    fn new(set: &'a InstructionSet, reader: R) -> Self {
+        Self {
+            set,
+            bytes: BufReader::new(reader).bytes(),
+            replay: VecDeque::new(),
+            buf: Vec::new(),
+            start: 0,
+        }
+    }
+
+    fn next_byte(&mut self) -> Option<std::io::Result<u8>> {
+        match self.replay.pop_front() {
+            Some(c) => Some(Ok(c)),
+            None => self.bytes.next(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Lexer<'_, R> {
+    type Item = anyhow::Result<Lexeme>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let c = match self.next_byte()? {
+                Ok(c) => c,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.buf.push(c);
+            match self.set.scan(&self.buf) {
+                Scan::Incomplete => (),
+                Scan::Complete(token) => {
+                    let span = self.start..self.start + self.buf.len();
+                    self.start = span.end;
+                    self.buf.clear();
+                    return Some(Ok(Lexeme { token, span }));
+                }
+                Scan::Invalid => {
+                    for &c in self.buf[1..].iter().rev() {
+                        self.replay.push_front(c);
+                    }
+                    self.start += 1;
+                    self.buf.clear();
+                }
+            }
+        }
+    }
+}
+
+struct Interpreter<'a> {
+    set: &'a InstructionSet,
+    conditionals: bool,
+    disabled: bool,
+    // Wide enough for any number of full-width results in practice, but
+    // still checked
+    total: i128,
+    executed: usize,
+    skipped: usize,
+    trace: Option<String>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(set: &'a InstructionSet, conditionals: bool) -> Self {
+        Self {
+            set,
+            conditionals,
+            disabled: false,
+            total: 0,
+            executed: 0,
+            skipped: 0,
+            trace: None,
+        }
+    }
+
+    fn with_trace(self) -> Self {
+        Self {
+            trace: Some(String::new()),
+            ..self
+        }
+    }
+
+    fn execute(&mut self, lexeme: Lexeme) -> anyhow::Result<()> {
+        match lexeme.token {
+            Token::Do => self.disabled = false,
+            Token::Dont => self.disabled = self.conditionals,
+            Token::Instruction(..) if self.disabled => self.skipped += 1,
+            Token::Instruction(index, operands) => {
+                let instruction = &self.set.instructions[index];
+                let result = instruction.evaluate(&operands);
+                self.total = self
+                    .total
+                    .checked_add(result.into())
+                    .ok_or_else(|| anyhow!("Total overflows at {}", lexeme.span.start))?;
+                self.executed += 1;
+                if let Some(trace) = self.trace.as_mut() {
+                    trace.push_str(&format!(
+                        "{}..{} {}({}) = {}\n",
+                        lexeme.span.start,
+                        lexeme.span.end,
+                        instruction.name,
+                        operands.iter().join(","),
+                        result
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run(self, input: &Path) -> anyhow::Result<Self> {
+        self.run_reader(File::open(input)?)
+    }
+
+    fn run_reader<R: Read>(mut self, reader: R) -> anyhow::Result<Self> {
+        for lexeme in Lexer::new(self.set, reader) {
+            self.execute(lexeme?)?;
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Class {
+    Literal(u8),
+    Digit,
+}
+
+impl Class {
+    fn matches(&self, c: u8) -> bool {
+        match self {
+            Class::Literal(l) => *l == c,
+            Class::Digit => c.is_ascii_digit(),
+        }
+    }
+
+    // Digits that have to be made up are filled with zeros
+    fn repair(&self, c: Option<u8>) -> u8 {
+        match (self, c) {
+            (Class::Literal(l), _) => *l,
+            (Class::Digit, Some(c)) if c.is_ascii_digit() => c,
+            (Class::Digit, _) => b'0',
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Edit {
+    Keep,
+    Drop,
+    Insert,
+}
+
+impl Instruction {
+    // Each element is a character class and whether it may be left out
+    fn pattern(&self) -> Vec<(Class, bool)> {
+        let mut pattern: Vec<(Class, bool)> = self
+            .name
+            .bytes()
+            .chain([b'('])
+            .map(|c| (Class::Literal(c), false))
+            .collect();
+        for i in 0..self.arity {
+            pattern.push((Class::Digit, false));
+            pattern.extend((1..self.max_digits).map(|_| (Class::Digit, true)));
+            let separator = if i + 1 == self.arity { b')' } else { b',' };
+            pattern.push((Class::Literal(separator), false));
+        }
+        pattern
+    }
+
+    // At most `max_edits` of the first `max_edits + 1` bytes of `name(` can
+    // be edited, so one of them is kept within the next `2 * max_edits + 1`
+    // bytes of text. Cheap enough to try before the full alignment.
+    fn may_start(&self, text: &[u8], max_edits: usize) -> bool {
+        let literal: Vec<u8> = self.name.bytes().chain([b'(']).collect();
+        if literal.len() <= max_edits {
+            return true;
+        }
+        let window = &text[..text.len().min(2 * max_edits + 1)];
+        literal[..=max_edits].iter().any(|c| window.contains(c))
+    }
+
+    // Finds the cheapest repair of a prefix of `text` into this instruction,
+    // preferring fewer made-up digits and then longer spans on ties.
+    fn nearest(&self, text: &[u8], max_edits: usize) -> Option<(usize, usize, Vec<u8>)> {
+        let pattern = self.pattern();
+        let text = &text[..text.len().min(pattern.len() + max_edits)];
+        let (rows, cols) = (text.len() + 1, pattern.len() + 1);
+        let mut cost = vec![vec![(usize::MAX, usize::MAX); cols]; rows];
+        let mut edit = vec![vec![Edit::Keep; cols]; rows];
+        cost[0][0] = (0, 0);
+
+        for t in 0..rows {
+            for p in 0..cols {
+                let (edits, invented) = cost[t][p];
+                if edits > max_edits {
+                    continue;
+                }
+                let mut relax = |t: usize, p: usize, next: (usize, usize), e: Edit| {
+                    if next < cost[t][p] {
+                        cost[t][p] = next;
+                        edit[t][p] = e;
+                    }
+                };
+                // A leading drop is left to the scan starting one byte later
+                if t < text.len() && p > 0 {
+                    relax(t + 1, p, (edits + 1, invented), Edit::Drop);
+                }
+                if p < pattern.len() {
+                    let (class, optional) = pattern[p];
+                    let made_up = (class == Class::Digit && !optional) as usize;
+                    let step = if optional { 0 } else { 1 };
+                    relax(t, p + 1, (edits + step, invented + made_up), Edit::Insert);
+                    if t < text.len() {
+                        let matched = class.matches(text[t]);
+                        let made_up = (class == Class::Digit && !matched) as usize;
+                        relax(
+                            t + 1,
+                            p + 1,
+                            (edits + !matched as usize, invented + made_up),
+                            Edit::Keep,
+                        );
+                    }
+                }
+            }
+        }
+
+        let (end, (edits, _)) = (0..rows)
+            .map(|t| (t, cost[t][pattern.len()]))
+            .filter(|(_, (edits, _))| (1..=max_edits).contains(edits))
+            .min_by_key(|&(t, c)| (c, std::cmp::Reverse(t)))?;
+
+        let mut repaired = Vec::new();
+        let (mut t, mut p) = (end, pattern.len());
+        while t > 0 || p > 0 {
+            match edit[t][p] {
+                Edit::Drop => t -= 1,
+                Edit::Insert => {
+                    p -= 1;
+                    if !pattern[p].1 {
+                        repaired.push(pattern[p].0.repair(None));
+                    }
+                }
+                Edit::Keep => {
+                    t -= 1;
+                    p -= 1;
+                    repaired.push(pattern[p].0.repair(Some(text[t])));
+                }
+            }
+        }
+        repaired.reverse();
+        Some((end, edits, repaired))
+    }
+}
+
+struct Recovery {
+    span: Range<usize>,
+    edits: usize,
+    original: String,
+    repaired: String,
+    lexeme: Lexeme,
+}
+
+impl InstructionSet {
+    fn recover(&self, text: &[u8], exact: &[Lexeme], max_edits: usize) -> Vec<Recovery> {
+        let gaps = exact
+            .iter()
+            .map(|l| l.span.start)
+            .chain([text.len()])
+            .zip([0].into_iter().chain(exact.iter().map(|l| l.span.end)));
+
+        let mut recovered = Vec::new();
+        for (gap_end, gap_start) in gaps {
+            let mut i = gap_start;
+            while i < gap_end {
+                let best = self
+                    .instructions
+                    .iter()
+                    .filter(|instruction| instruction.may_start(&text[i..gap_end], max_edits))
+                    .filter_map(|instruction| instruction.nearest(&text[i..gap_end], max_edits))
+                    .min_by_key(|&(end, edits, _)| (edits, std::cmp::Reverse(end)));
+                let Some((len, edits, repaired)) = best else {
+                    i += 1;
+                    continue;
+                };
+                if let Scan::Complete(token) = self.scan(&repaired) {
+                    let span = i..i + len;
+                    recovered.push(Recovery {
+                        original: String::from_utf8_lossy(&text[span.clone()]).into_owned(),
+                        repaired: String::from_utf8_lossy(&repaired).into_owned(),
+                        span: span.clone(),
+                        edits,
+                        lexeme: Lexeme { token, span },
+                    });
+                }
+                i += len.max(1);
+            }
+        }
+        recovered
+    }
+}
+
+pub fn part_a(input: &Path, set: &InstructionSet) -> anyhow::Result<i128> {
+    Ok(Interpreter::new(set, false).run(input)?.total)
 }
 
-fn score_strings(contents: &str) -> anyhow::Result<u32> {
-    let list = find_mul_strings(contents)?;
-    Ok(list.into_iter().map(|(a, b)| a as u32 * b as u32).sum())
+pub fn part_b(input: &Path, set: &InstructionSet) -> anyhow::Result<i128> {
+    Ok(Interpreter::new(set, true).run(input)?.total)
 }
 
-pub fn part_a(input: &Path) -> anyhow::Result<u32> {
-    let contents = read_to_string(input)?;
-    score_strings(&contents)
+pub fn summary(input: &Path, set: &InstructionSet) -> anyhow::Result<String> {
+    let interpreter = Interpreter::new(set, true).run(input)?;
+    Ok(format!(
+        "total {}, {} instructions executed, {} skipped while disabled",
+        interpreter.total, interpreter.executed, interpreter.skipped
+    ))
 }
 
-pub fn part_b(input: &Path) -> anyhow::Result<u32> {
-    // Insert a do() at the start to make processing easier
-    let contents = format!("do(){}", read_to_string(input)?);
+pub fn trace(input: &Path, set: &InstructionSet) -> anyhow::Result<String> {
+    let interpreter = Interpreter::new(set, true).with_trace().run(input)?;
+    Ok(interpreter.trace.unwrap_or_default())
+}
+
+// Unlike the other modes this reads the whole input into memory, since the
+// gaps between exact instructions are searched after lexing
+pub fn recover(input: &Path, set: &InstructionSet, max_edits: usize) -> anyhow::Result<String> {
+    let text = std::fs::read(input)?;
+    let exact: Vec<Lexeme> = Lexer::new(set, &text[..]).try_collect()?;
+    let recovered = set.recover(&text, &exact, max_edits);
+
+    let mut out = String::new();
+    for r in &recovered {
+        out.push_str(&format!(
+            "{}..{} {:?} -> {:?} (distance {})\n",
+            r.span.start, r.span.end, r.original, r.repaired, r.edits
+        ));
+    }
+
+    let total = Interpreter::new(set, true).run_reader(&text[..])?.total;
+    let mut recovered_total = Interpreter::new(set, true);
+    let mut recovered = recovered.into_iter().map(|r| r.lexeme).peekable();
+    for lexeme in exact {
+        while let Some(r) = recovered.next_if(|r| r.span.start < lexeme.span.start) {
+            recovered_total.execute(r)?;
+        }
+        recovered_total.execute(lexeme)?;
+    }
+    for r in recovered {
+        recovered_total.execute(r)?;
+    }
 
-    contents.split("don't()").try_fold(0, |score, x| {
-        let (_, enabled) = x.split_once("do()").unwrap_or(("", ""));
-        Ok(score + score_strings(enabled)?)
-    })
+    out.push_str(&format!(
+        "total {}, with recovered instructions {}\n",
+        total, recovered_total.total
+    ));
+    Ok(out)
 }
@@ -1,106 +1,529 @@
-use std::collections::HashSet;
+use aho_corasick::AhoCorasick;
+use anyhow::anyhow;
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
 
 fn read_to_string(input: &Path) -> anyhow::Result<String> {
     std::fs::read_to_string(input).map_err(Into::into)
 }
 
 struct Grid {
-    letters: String,
+    cells: Vec<u8>,
     width: usize,
     height: usize,
 }
 
 impl Grid {
-    fn new(letters: String) -> Self {
-        let width = letters.find("\n").unwrap_or(letters.len());
-        let height = letters.split("\n").count();
-        let letters = letters.replace("\n", "");
+    // Cells are stored as bytes, so only ASCII grids are accepted
+    fn new(letters: &str) -> anyhow::Result<Self> {
+        let width = letters.lines().next().map_or(0, str::len);
+        let mut cells = Vec::with_capacity(letters.len());
+        let mut height = 0;
+        for (y, line) in letters.lines().enumerate() {
+            if let Some((x, c)) = line.chars().enumerate().find(|(_, c)| !c.is_ascii()) {
+                return Err(anyhow!("Non-ASCII character {:?} at {},{}", c, x, y));
+            }
+            if line.len() != width {
+                return Err(anyhow!(
+                    "Line {} has width {}, expected {}",
+                    y,
+                    line.len(),
+                    width
+                ));
+            }
+            cells.extend_from_slice(line.as_bytes());
+            height += 1;
+        }
 
-        Self {
-            letters,
+        Ok(Self {
+            cells,
             width,
             height,
-        }
+        })
     }
 
-    fn get(&self, (x, y): (usize, usize)) -> Option<char> {
+    fn get(&self, (x, y): (usize, usize)) -> Option<u8> {
         if x >= self.width || y >= self.height {
             return None;
         }
-        let index = y * self.width + x;
-        self.letters.chars().nth(index)
+        Some(self.cells[y * self.width + x])
     }
 
     fn to_point(&self, index: usize) -> (usize, usize) {
         (index % self.width, index / self.width)
     }
 
-    fn check_str(&self, curr: (usize, usize), direction: (i8, i8), rest: &str) -> bool {
-        let spot = self.get(curr);
-        if rest.is_empty() || (rest.len() == 1 && spot == rest.chars().nth(0)) {
-            return true;
-        }
-        if spot != Some(rest.chars().nth(0).unwrap()) {
-            return false;
+    // Every maximal line of cells running in `direction`, from its first cell
+    fn lines(&self, direction: (isize, isize)) -> Vec<Vec<(usize, usize)>> {
+        let in_bounds = |(x, y): (isize, isize)| {
+            x >= 0 && y >= 0 && x < self.width as isize && y < self.height as isize
         };
-        let next = (
-            curr.0 as isize + direction.0 as isize,
-            curr.1 as isize + direction.1 as isize,
-        );
-        if next.0 < 0
-            || next.1 < 0
-            || next.0 >= self.width as isize
-            || next.1 >= self.height as isize
-        {
-            return false;
+        let mut lines = Vec::new();
+        for y in 0..self.height as isize {
+            for x in 0..self.width as isize {
+                if in_bounds((x - direction.0, y - direction.1)) {
+                    continue;
+                }
+                let mut line = Vec::new();
+                let mut curr = (x, y);
+                while in_bounds(curr) {
+                    line.push((curr.0 as usize, curr.1 as usize));
+                    curr = (curr.0 + direction.0, curr.1 + direction.1);
+                }
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    // Reversed words are added as extra patterns, so scanning each line once
+    // in four directions covers all eight.
+    fn search(&self, words: &[String]) -> anyhow::Result<Vec<Occurrence>> {
+        let patterns = words
+            .iter()
+            .cloned()
+            .chain(words.iter().map(|w| w.chars().rev().collect()));
+        let automaton = AhoCorasick::new(patterns)?;
+
+        let mut occurrences = Vec::new();
+        for direction in [(1, 0), (0, 1), (1, 1), (-1, 1)] {
+            for line in self.lines(direction) {
+                let text: Vec<u8> = line
+                    .iter()
+                    .map(|&(x, y)| self.cells[y * self.width + x])
+                    .collect();
+                for m in automaton.find_overlapping_iter(&text) {
+                    let id = m.pattern().as_usize();
+                    occurrences.push(if id < words.len() {
+                        Occurrence {
+                            start: line[m.start()],
+                            direction,
+                            word: id,
+                        }
+                    } else {
+                        Occurrence {
+                            start: line[m.end() - 1],
+                            direction: (-direction.0, -direction.1),
+                            word: id - words.len(),
+                        }
+                    });
+                }
+            }
         }
-        self.check_str((next.0 as usize, next.1 as usize), direction, &rest[1..])
+        Ok(occurrences)
     }
 }
 
-pub fn part_a(input: &Path) -> anyhow::Result<u32> {
-    let contents = read_to_string(input)?;
-    let grid = Grid::new(contents);
-    let mut count = 0;
+struct Occurrence {
+    start: (usize, usize),
+    direction: (isize, isize),
+    word: usize,
+}
 
-    for i in 0..grid.letters.len() {
-        println!("{:?}", grid.to_point(i));
-        for xi in -1..=1 {
-            for yi in -1..=1 {
-                if xi == 0 && yi == 0 {
-                    continue;
+const DIRECTIONS: [(isize, isize); 8] = [
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+];
+
+fn direction_name(direction: (isize, isize)) -> &'static str {
+    match direction {
+        (0, -1) => "N",
+        (1, -1) => "NE",
+        (1, 0) => "E",
+        (1, 1) => "SE",
+        (0, 1) => "S",
+        (-1, 1) => "SW",
+        (-1, 0) => "W",
+        _ => "NW",
+    }
+}
+
+fn read_words(input: &Path) -> anyhow::Result<Vec<String>> {
+    Ok(read_to_string(input)?
+        .split_whitespace()
+        .map(str::to_string)
+        .collect())
+}
+
+fn count_xmas(grid: &Grid) -> anyhow::Result<usize> {
+    Ok(grid.search(&["XMAS".to_string()])?.len())
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Symmetry {
+    None,
+    Rotations,
+    All,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct Template {
+    cells: Vec<Option<u8>>,
+    width: usize,
+    height: usize,
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    // Rows are separated by `/` and `.` matches any letter, e.g. `M.S/.A./M.S`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let rows: Vec<&str> = s.split('/').map(str::trim).collect();
+        let width = rows[0].len();
+        if width == 0 || rows.iter().any(|r| r.len() != width || !r.is_ascii()) {
+            return Err(anyhow!(
+                "Template rows must be non-empty ASCII of equal width"
+            ));
+        }
+        let cells = rows
+            .iter()
+            .flat_map(|r| r.bytes())
+            .map(|c| (c != b'.').then_some(c))
+            .collect();
+        Ok(Self {
+            cells,
+            width,
+            height: rows.len(),
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (y, row) in self.cells.chunks(self.width).enumerate() {
+            if y > 0 {
+                write!(f, "/")?;
+            }
+            for c in row {
+                write!(f, "{}", c.unwrap_or(b'.') as char)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Template {
+    fn map(&self, width: usize, height: usize, source: impl Fn(usize, usize) -> usize) -> Self {
+        let cells = (0..width * height)
+            .map(|i| self.cells[source(i % width, i / width)])
+            .collect();
+        Self {
+            cells,
+            width,
+            height,
+        }
+    }
+
+    fn rotate(&self) -> Self {
+        self.map(self.height, self.width, |x, y| {
+            (self.height - 1 - x) * self.width + y
+        })
+    }
+
+    fn reflect(&self) -> Self {
+        self.map(self.width, self.height, |x, y| {
+            y * self.width + self.width - 1 - x
+        })
+    }
+
+    // Distinct orientations only, so symmetric templates aren't counted twice
+    fn variants(&self, symmetry: Symmetry) -> Vec<Self> {
+        let mut bases = vec![self.clone()];
+        if symmetry == Symmetry::All {
+            bases.push(self.reflect());
+        }
+        let mut variants: Vec<Self> = Vec::new();
+        for base in bases {
+            let mut curr = base;
+            for _ in 0..4 {
+                if !variants.contains(&curr) {
+                    variants.push(curr.clone());
                 }
-                if grid.check_str(grid.to_point(i), (xi, yi), "XMAS") {
-                    count += 1;
+                if symmetry == Symmetry::None {
+                    break;
                 }
+                curr = curr.rotate();
             }
         }
+        variants
+    }
+
+    fn matches_at(&self, grid: &Grid, (x, y): (usize, usize)) -> bool {
+        if x + self.width > grid.width || y + self.height > grid.height {
+            return false;
+        }
+        self.cells.iter().enumerate().all(|(i, c)| {
+            c.is_none_or(|c| grid.get((x + i % self.width, y + i / self.width)) == Some(c))
+        })
+    }
+}
+
+impl Grid {
+    // Top-left corners of every match, with the index of the matching variant
+    fn find_template(&self, variants: &[Template]) -> Vec<((usize, usize), usize)> {
+        (0..self.cells.len())
+            .map(|i| self.to_point(i))
+            .flat_map(|p| {
+                variants
+                    .iter()
+                    .enumerate()
+                    .filter(move |(_, t)| t.matches_at(self, p))
+                    .map(move |(v, _)| (p, v))
+            })
+            .collect()
+    }
+}
+
+fn count_x_mas(grid: &Grid) -> anyhow::Result<usize> {
+    let template: Template = "M.S/.A./M.S".parse()?;
+    Ok(grid
+        .find_template(&template.variants(Symmetry::Rotations))
+        .len())
+}
+
+pub fn part_a(input: &Path) -> anyhow::Result<usize> {
+    let grid = Grid::new(&read_to_string(input)?)?;
+    count_xmas(&grid)
+}
+
+pub fn part_b(input: &Path) -> anyhow::Result<usize> {
+    let grid = Grid::new(&read_to_string(input)?)?;
+    count_x_mas(&grid)
+}
+
+pub fn search(input: &Path, words: &Path) -> anyhow::Result<String> {
+    let grid = Grid::new(&read_to_string(input)?)?;
+    let words = read_words(words)?;
+    let mut occurrences = grid.search(&words)?;
+    occurrences.sort_by_key(|o| (o.start.1, o.start.0, o.word));
+
+    let mut out = String::new();
+    for o in occurrences {
+        out.push_str(&format!(
+            "{},{} {} {}\n",
+            o.start.0,
+            o.start.1,
+            direction_name(o.direction),
+            words[o.word]
+        ));
+    }
+    Ok(out)
+}
+
+pub fn template(input: &Path, template: &str, symmetry: Symmetry) -> anyhow::Result<String> {
+    let grid = Grid::new(&read_to_string(input)?)?;
+    let variants = template.parse::<Template>()?.variants(symmetry);
+    let matches = grid.find_template(&variants);
+
+    let mut out = String::new();
+    for ((x, y), v) in &matches {
+        out.push_str(&format!("{},{} {}\n", x, y, variants[*v]));
     }
-    Ok(count)
+    out.push_str(&format!("{} matches\n", matches.len()));
+    Ok(out)
 }
 
-pub fn part_b(input: &Path) -> anyhow::Result<u32> {
-    let contents = read_to_string(input)?;
-    let grid = Grid::new(contents);
-    let mut count = 0;
-    let goal = HashSet::from([Some('M'), Some('S')]);
+const EMPTY: u8 = 0;
+const PLACEMENT_ATTEMPTS: usize = 1000;
 
-    for i in 0..grid.letters.len() {
-        let (x, y) = grid.to_point(i);
+impl Grid {
+    fn cells_along(
+        &self,
+        (x, y): (isize, isize),
+        direction: (isize, isize),
+        len: usize,
+    ) -> Option<Vec<usize>> {
+        (0..len as isize)
+            .map(|i| {
+                let (x, y) = (x + i * direction.0, y + i * direction.1);
+                (x >= 0 && y >= 0 && x < self.width as isize && y < self.height as isize)
+                    .then(|| y as usize * self.width + x as usize)
+            })
+            .collect()
+    }
 
-        println!("{:?}", (x, y));
-        if x == 0 || y == 0 || x == grid.width - 1 || y == grid.height - 1 {
-            continue;
+    fn place(&mut self, word: &[u8], rng: &mut StdRng) -> bool {
+        for _ in 0..PLACEMENT_ATTEMPTS {
+            let start = (
+                rng.gen_range(0..self.width as isize),
+                rng.gen_range(0..self.height as isize),
+            );
+            let direction = *DIRECTIONS.choose(rng).unwrap();
+            let Some(indices) = self.cells_along(start, direction, word.len()) else {
+                continue;
+            };
+            if indices
+                .iter()
+                .zip(word)
+                .all(|(&i, &c)| self.cells[i] == EMPTY || self.cells[i] == c)
+            {
+                indices
+                    .iter()
+                    .zip(word)
+                    .for_each(|(&i, &c)| self.cells[i] = c);
+                return true;
+            }
         }
-        if grid.get((x, y)) != Some('A') {
-            continue;
+        false
+    }
+
+    // Whether some word now runs through `index` using only filled cells
+    fn completes_word(&self, index: usize, words: &[String]) -> bool {
+        let (x, y) = self.to_point(index);
+        words.iter().any(|word| {
+            DIRECTIONS.iter().any(|&direction| {
+                (0..word.len() as isize).any(|k| {
+                    let start = (x as isize - k * direction.0, y as isize - k * direction.1);
+                    self.cells_along(start, direction, word.len())
+                        .is_some_and(|indices| {
+                            indices
+                                .iter()
+                                .zip(word.bytes())
+                                .all(|(&i, c)| self.cells[i] == c)
+                        })
+                })
+            })
+        })
+    }
+
+    // Letters from the words make the best decoys. One that is in no word
+    // can never complete an occurrence, so it is the fallback when none fits.
+    fn fill(&mut self, words: &[String], rng: &mut StdRng) -> anyhow::Result<()> {
+        let alphabet: Vec<u8> = words.iter().flat_map(|w| w.bytes()).unique().collect();
+        let unused = |range: RangeInclusive<u8>| -> Vec<u8> {
+            range.filter(|c| !alphabet.contains(c)).collect()
+        };
+        let mut outsiders = match alphabet.iter().any(u8::is_ascii_lowercase) {
+            true => unused(b'a'..=b'z'),
+            false => unused(b'A'..=b'Z'),
+        };
+        if outsiders.is_empty() {
+            outsiders = unused(b'!'..=b'~');
         }
-        let ul_dr_set = HashSet::from([grid.get((x - 1, y - 1)), grid.get((x + 1, y + 1))]);
-        let ur_dl_set = HashSet::from([grid.get((x + 1, y - 1)), grid.get((x - 1, y + 1))]);
-        if ul_dr_set == goal && ur_dl_set == goal {
-            count += 1;
+        for index in 0..self.cells.len() {
+            if self.cells[index] != EMPTY {
+                continue;
+            }
+            let mut letters = alphabet.clone();
+            letters.shuffle(rng);
+            let fallback = outsiders.choose(rng).copied();
+            let letter = letters.into_iter().chain(fallback).find(|&c| {
+                self.cells[index] = c;
+                !self.completes_word(index, words)
+            });
+            if letter.is_none() {
+                let (x, y) = self.to_point(index);
+                return Err(anyhow!("No letter fits at {},{}", x, y));
+            }
         }
+        Ok(())
+    }
+}
+
+// Hides every word, then fills the gaps without adding occurrences beyond
+// those the placed words already form among themselves.
+pub fn generate(
+    words: &Path,
+    width: usize,
+    height: usize,
+    seed: Option<u64>,
+) -> anyhow::Result<String> {
+    if width == 0 || height == 0 {
+        return Err(anyhow!(
+            "Puzzle must be at least 1x1, got {}x{}",
+            width,
+            height
+        ));
+    }
+    let words = read_words(words)?;
+    if let Some(word) = words.iter().find(|w| w.is_empty() || !w.is_ascii()) {
+        return Err(anyhow!("Word {:?} is not ASCII", word));
+    }
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut grid = Grid {
+        cells: vec![EMPTY; width * height],
+        width,
+        height,
+    };
+
+    let by_length = words.iter().sorted_by_key(|w| std::cmp::Reverse(w.len()));
+    for word in by_length {
+        if !grid.place(word.as_bytes(), &mut rng) {
+            return Err(anyhow!("Could not place {}", word));
+        }
+    }
+    let expected = grid.search(&words)?.len();
+    grid.fill(&words, &mut rng)?;
+    let found = grid.search(&words)?.len();
+    if found != expected {
+        return Err(anyhow!(
+            "Expected {} occurrences, found {}",
+            expected,
+            found
+        ));
+    }
+
+    let mut out = String::new();
+    for row in grid.cells.chunks(width) {
+        out.push_str(&String::from_utf8_lossy(row));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+// Tiles the input grid up to each size and times both solvers, so the time
+// per cell should stay flat as the grid grows.
+pub fn bench(input: &Path) -> anyhow::Result<String> {
+    let tile = Grid::new(&read_to_string(input)?)?;
+    if tile.cells.is_empty() {
+        return Err(anyhow!("Empty grid"));
+    }
+    let mut out =
+        String::from("size,cells,part_a_ms,part_b_ms,part_a_ns_per_cell,part_b_ns_per_cell\n");
+    for size in [250, 500, 1000, 2000] {
+        let cells = (0..size * size)
+            .map(|i| tile.cells[(i / size % tile.height) * tile.width + i % size % tile.width])
+            .collect();
+        let grid = Grid {
+            cells,
+            width: size,
+            height: size,
+        };
+
+        let start = Instant::now();
+        count_xmas(&grid)?;
+        let a = start.elapsed();
+        let start = Instant::now();
+        count_x_mas(&grid)?;
+        let b = start.elapsed();
+
+        let n = (size * size) as f64;
+        out.push_str(&format!(
+            "{}x{},{},{:.1},{:.1},{:.1},{:.1}\n",
+            size,
+            size,
+            size * size,
+            a.as_secs_f64() * 1e3,
+            b.as_secs_f64() * 1e3,
+            a.as_nanos() as f64 / n,
+            b.as_nanos() as f64 / n,
+        ));
     }
-    Ok(count)
+    Ok(out)
 }
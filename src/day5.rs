@@ -1,5 +1,11 @@
+use anyhow::anyhow;
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::path::Path;
 
+const MAX_PLACEMENTS: usize = 1 << 20;
+
 fn read_to_string(input: &Path) -> anyhow::Result<String> {
     std::fs::read_to_string(input).map_err(Into::into)
 }
@@ -22,7 +28,8 @@ fn get_parts(contents: &str) -> anyhow::Result<(OrderList, PagesList)> {
         .collect();
 
     let pages_parts: PagesList = pages
-        .split("\n")
+        .lines()
+        .filter(|x| !x.is_empty())
         .map(|x| {
             let p: Vec<usize> = x
                 .split(",")
@@ -36,17 +43,25 @@ fn get_parts(contents: &str) -> anyhow::Result<(OrderList, PagesList)> {
 }
 
 struct Order {
-    order: OrderList,
+    after: HashMap<usize, HashSet<usize>>,
 }
 
 impl Order {
     fn new(order: OrderList) -> Self {
-        Self { order }
+        let mut after: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for (x, y) in order {
+            after.entry(x).or_default().insert(y);
+        }
+        Self { after }
+    }
+
+    fn precedes(&self, x: usize, y: usize) -> bool {
+        self.after.get(&x).is_some_and(|after| after.contains(&y))
     }
 
     fn compare(&self, x: usize, y: usize) -> Option<bool> {
-        let ordered = self.order.contains(&(x, y));
-        let reversed = self.order.contains(&(y, x));
+        let ordered = self.precedes(x, y);
+        let reversed = self.precedes(y, x);
         if ordered | reversed {
             Some(ordered)
         } else {
@@ -55,6 +70,153 @@ impl Order {
     }
 }
 
+// The rules restricted to one update, as edges between page positions
+struct Precedence {
+    successors: Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+}
+
+impl Precedence {
+    fn new(order: &Order, pages: &[usize]) -> Self {
+        let positions: HashMap<usize, usize> =
+            pages.iter().enumerate().map(|(i, p)| (*p, i)).collect();
+        let mut successors = vec![Vec::new(); pages.len()];
+        let mut predecessors = vec![Vec::new(); pages.len()];
+        for (i, page) in pages.iter().enumerate() {
+            for next in order.after.get(page).into_iter().flatten() {
+                if let Some(&j) = positions.get(next) {
+                    successors[i].push(j);
+                    predecessors[j].push(i);
+                }
+            }
+        }
+        Self {
+            successors,
+            predecessors,
+        }
+    }
+
+    // Kahn's algorithm, always taking the available position with the
+    // smallest key
+    fn sort_by_key<K: Ord>(&self, key: impl Fn(usize) -> K) -> Result<Vec<usize>, Vec<usize>> {
+        let mut in_degree: Vec<usize> = self.predecessors.iter().map(Vec::len).collect();
+        let mut ready: BinaryHeap<Reverse<(K, usize)>> = (0..in_degree.len())
+            .filter(|&i| in_degree[i] == 0)
+            .map(|i| Reverse((key(i), i)))
+            .collect();
+        let mut sorted = Vec::with_capacity(in_degree.len());
+
+        while let Some(Reverse((_, i))) = ready.pop() {
+            sorted.push(i);
+            for &j in &self.successors[i] {
+                in_degree[j] -= 1;
+                if in_degree[j] == 0 {
+                    ready.push(Reverse((key(j), j)));
+                }
+            }
+        }
+
+        if sorted.len() == in_degree.len() {
+            Ok(sorted)
+        } else {
+            Err(self.find_cycle(&in_degree))
+        }
+    }
+
+    // Pages the rules don't constrain keep their original order
+    fn sort(&self) -> Result<Vec<usize>, Vec<usize>> {
+        self.sort_by_key(|i| i)
+    }
+
+    fn reachable(&self, start: usize, edges: &[Vec<usize>]) -> usize {
+        let mut seen = HashSet::from([start]);
+        let mut stack = vec![start];
+        while let Some(curr) = stack.pop() {
+            for &next in &edges[curr] {
+                if seen.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+        seen.len() - 1
+    }
+
+    // Positions a page can take range from its number of ancestors to the
+    // length minus its number of descendants
+    fn can_take_position(&self, i: usize, position: usize) -> bool {
+        let before = self.reachable(i, &self.predecessors);
+        let after = self.reachable(i, &self.successors);
+        before <= position && position + after < self.successors.len()
+    }
+
+    // Counts linear extensions by memoizing over the sets of positions
+    // already placed, which must always be closed under predecessors. Wide
+    // updates have exponentially many such sets, so give up past a limit.
+    fn count_orderings(&self) -> anyhow::Result<u128> {
+        if self.successors.len() > 64 {
+            return Err(anyhow!("Can't count orderings of more than 64 pages"));
+        }
+        let required: Vec<u64> = self
+            .predecessors
+            .iter()
+            .map(|p| p.iter().fold(0, |mask, i| mask | 1 << i))
+            .collect();
+        let full = u64::MAX
+            .checked_shr(64 - self.successors.len() as u32)
+            .unwrap_or(0);
+
+        fn count(
+            placed: u64,
+            full: u64,
+            required: &[u64],
+            memo: &mut HashMap<u64, u128>,
+        ) -> anyhow::Result<u128> {
+            if placed == full {
+                return Ok(1);
+            }
+            if let Some(&n) = memo.get(&placed) {
+                return Ok(n);
+            }
+            if memo.len() >= MAX_PLACEMENTS {
+                return Err(anyhow!(
+                    "Too many partial orderings to count, more than {}",
+                    MAX_PLACEMENTS
+                ));
+            }
+            let mut n: u128 = 0;
+            for i in 0..required.len() {
+                if placed & 1 << i == 0 && required[i] & !placed == 0 {
+                    n = n
+                        .checked_add(count(placed | 1 << i, full, required, memo)?)
+                        .ok_or_else(|| anyhow!("Number of orderings overflows 128 bits"))?;
+                }
+            }
+            memo.insert(placed, n);
+            Ok(n)
+        }
+        count(0, full, &required, &mut HashMap::new())
+    }
+
+    // Every unsorted position still has an unsorted predecessor, so walking
+    // predecessors from any of them must eventually repeat
+    fn find_cycle(&self, in_degree: &[usize]) -> Vec<usize> {
+        let mut curr = (0..in_degree.len()).find(|&i| in_degree[i] > 0).unwrap();
+        let mut seen = HashMap::new();
+        let mut path = Vec::new();
+        while !seen.contains_key(&curr) {
+            seen.insert(curr, path.len());
+            path.push(curr);
+            curr = *self.predecessors[curr]
+                .iter()
+                .find(|&&p| in_degree[p] > 0)
+                .unwrap();
+        }
+        let mut cycle = path.split_off(seen[&curr]);
+        cycle.reverse();
+        cycle
+    }
+}
+
 fn pages_are_ordered(order: &Order, pages: &[usize]) -> bool {
     for first in 0..pages.len() {
         for second in first + 1..pages.len() {
@@ -68,23 +230,60 @@ fn pages_are_ordered(order: &Order, pages: &[usize]) -> bool {
     true
 }
 
-fn order_pages(order: &Order, pages: &mut Vec<usize>) -> Vec<usize> {
-    let mut ordered = Vec::with_capacity(pages.len());
+// Position pairs (i, j) with i < j whose pages break a `pages[j]|pages[i]` rule
+fn violations(order: &Order, pages: &[usize]) -> Vec<(usize, usize)> {
+    (0..pages.len())
+        .tuple_combinations()
+        .filter(|&(i, j)| order.compare(pages[i], pages[j]) == Some(false))
+        .collect()
+}
 
-    while !pages.is_empty() {
-        let lowest = *pages
-            .iter()
-            .find(|x| {
-                pages
-                    .iter()
-                    .all(|y| *x == y || order.compare(**x, *y).unwrap_or(true))
-            })
-            .unwrap();
-        pages.remove(pages.iter().position(|x| *x == lowest).unwrap());
-        ordered.push(lowest);
-    }
+// Drops violations already implied by a chain of other violations, e.g.
+// breaking 75|47 and 47|61 forces 75|61 to be broken too
+fn minimal_violations(violations: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let reachable_without = |skip: (usize, usize)| {
+        let (target, start) = skip;
+        let mut stack = vec![start];
+        let mut seen = HashSet::from([start]);
+        while let Some(curr) = stack.pop() {
+            for &(i, j) in violations {
+                if j == curr && (i, j) != skip && seen.insert(i) {
+                    if i == target {
+                        return true;
+                    }
+                    stack.push(i);
+                }
+            }
+        }
+        false
+    };
+    violations
+        .iter()
+        .copied()
+        .filter(|&v| !reachable_without(v))
+        .collect()
+}
 
-    ordered
+fn order_pages(order: &Order, pages: &[usize]) -> anyhow::Result<Vec<usize>> {
+    to_pages(Precedence::new(order, pages).sort(), pages)
+}
+
+fn to_pages(sorted: Result<Vec<usize>, Vec<usize>>, pages: &[usize]) -> anyhow::Result<Vec<usize>> {
+    match sorted {
+        Ok(sorted) => Ok(sorted.into_iter().map(|i| pages[i]).collect()),
+        Err(cycle) => {
+            let rules = cycle
+                .iter()
+                .circular_tuple_windows()
+                .map(|(&x, &y)| format!("{}|{}", pages[x], pages[y]))
+                .join(", ");
+            Err(anyhow!(
+                "Rules form a cycle ({}) in update {}",
+                rules,
+                pages.iter().join(",")
+            ))
+        }
+    }
 }
 
 pub fn part_a(input: &Path) -> anyhow::Result<usize> {
@@ -95,7 +294,6 @@ pub fn part_a(input: &Path) -> anyhow::Result<usize> {
     let order = Order::new(order);
     for pages in pages_list {
         if pages_are_ordered(&order, &pages) {
-            println!("{:?} ordered", pages);
             count += pages[pages.len() / 2]
         }
     }
@@ -110,9 +308,69 @@ pub fn part_b(input: &Path) -> anyhow::Result<usize> {
 
     for pages in pages_list {
         if !pages_are_ordered(&order, &pages) {
-            let ordered = order_pages(&order, &mut pages.clone());
+            let ordered = order_pages(&order, &pages)?;
             count += ordered[ordered.len() / 2];
         }
     }
     Ok(count)
 }
+
+pub fn explain(input: &Path) -> anyhow::Result<String> {
+    let contents = read_to_string(input)?;
+    let (order, pages_list) = get_parts(&contents)?;
+    let order = Order::new(order);
+    let mut out = String::new();
+
+    for (n, pages) in pages_list.iter().enumerate() {
+        let violations = violations(&order, pages);
+        if violations.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("update {}: {}\n", n + 1, pages.iter().join(",")));
+        for (i, j) in minimal_violations(&violations) {
+            out.push_str(&format!(
+                "  violates {}|{} (positions {} and {})\n",
+                pages[j], pages[i], j, i
+            ));
+        }
+        let ordered = order_pages(&order, pages)?;
+        out.push_str(&format!("  reordered to {}\n", ordered.iter().join(",")));
+        for (to, page) in ordered.iter().enumerate() {
+            let from = pages.iter().position(|p| p == page).unwrap();
+            if from != to {
+                out.push_str(&format!("  moved {}: {} -> {}\n", page, from, to));
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn orderings(input: &Path) -> anyhow::Result<String> {
+    let contents = read_to_string(input)?;
+    let (order, pages_list) = get_parts(&contents)?;
+    let order = Order::new(order);
+    let mut out = String::new();
+
+    for (n, pages) in pages_list.iter().enumerate() {
+        let precedence = Precedence::new(&order, pages);
+        let smallest = to_pages(precedence.sort_by_key(|i| pages[i]), pages)?;
+        let largest = to_pages(precedence.sort_by_key(|i| Reverse(pages[i])), pages)?;
+        let middle = pages.len() / 2;
+        let (low, high) = (0..pages.len())
+            .filter(|&i| precedence.can_take_position(i, middle))
+            .map(|i| pages[i])
+            .minmax()
+            .into_option()
+            .ok_or(anyhow!("Update {} is empty", n + 1))?;
+        out.push_str(&format!(
+            "update {}: {} orderings, smallest {}, largest {}, middle {}..{}\n",
+            n + 1,
+            precedence.count_orderings()?,
+            smallest.iter().join(","),
+            largest.iter().join(","),
+            low,
+            high
+        ));
+    }
+    Ok(out)
+}
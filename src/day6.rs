@@ -1,4 +1,6 @@
-use std::collections::HashSet;
+use anyhow::anyhow;
+use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::Path;
 
 fn read_to_string(input: &Path) -> anyhow::Result<String> {
@@ -13,7 +15,77 @@ enum Direction {
     Left,
 }
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+
+impl Direction {
+    fn is_vertical(&self) -> bool {
+        matches!(self, Direction::Up | Direction::Down)
+    }
+
+    fn symbol(&self) -> char {
+        match self {
+            Direction::Up => '^',
+            Direction::Right => '>',
+            Direction::Down => 'v',
+            Direction::Left => '<',
+        }
+    }
+
+    fn from_symbol(c: u8) -> Option<Self> {
+        DIRECTIONS.into_iter().find(|d| d.symbol() as u8 == c)
+    }
+
+    fn turn(self) -> Self {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+
+    fn reverse(self) -> Self {
+        self.turn().turn()
+    }
+}
+
+#[derive(Copy, Clone, clap::ValueEnum)]
+pub enum Turn {
+    Right,
+    Left,
+    Back,
+}
+
+pub struct Rules {
+    turn: Turn,
+    obstacles: Vec<u8>,
+    wrap: bool,
+}
+
+impl Rules {
+    pub fn new(turn: Turn, obstacles: &str, wrap: bool) -> Self {
+        Self {
+            turn,
+            obstacles: obstacles.bytes().collect(),
+            wrap,
+        }
+    }
+
+    fn turn(&self, direction: Direction) -> Direction {
+        match self.turn {
+            Turn::Right => direction.turn(),
+            Turn::Left => direction.reverse().turn(),
+            Turn::Back => direction.reverse(),
+        }
+    }
+}
+
+#[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Copy, Clone)]
 struct Point {
     x: isize,
     y: isize,
@@ -46,114 +118,333 @@ impl GuardLocation {
     }
 }
 
-struct Grid {
-    grid: Vec<Vec<char>>,
-    guard: Option<GuardLocation>,
-    visited: HashSet<Point>,
-    guard_locations: HashSet<GuardLocation>,
-    looped: bool,
+struct Grid<'a> {
+    width: usize,
+    height: usize,
+    obstacles: Vec<Option<u8>>,
+    guards: Vec<GuardLocation>,
+    rules: &'a Rules,
+    // For every cell and direction, where the guard stops in front of the
+    // next obstacle, or None if it walks off the map
+    jumps: Vec<[Option<Point>; 4]>,
 }
 
-impl Grid {
-    fn new(grid: String) -> Self {
-        let guard = grid.replace("\n", "").find('^').unwrap();
-        let grid = grid.replace("^", ".");
-        let grid: Vec<Vec<char>> = grid.lines().map(|x| x.chars().collect()).collect();
-        let guard = GuardLocation {
-            location: Point {
-                x: (guard % grid[0].len()) as isize,
-                y: (guard / grid[0].len()) as isize,
-            },
-            direction: Direction::Up,
+impl<'a> Grid<'a> {
+    fn new(grid: &str, rules: &'a Rules) -> anyhow::Result<Self> {
+        let rows: Vec<&str> = grid.lines().collect();
+        let width = rows.first().map_or(0, |r| r.len());
+        let height = rows.len();
+        let mut obstacles = Vec::with_capacity(width * height);
+        let mut guards = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            if row.len() != width {
+                return Err(anyhow!("Row {} has the wrong width", y));
+            }
+            for (x, c) in row.bytes().enumerate() {
+                let is_obstacle = rules.obstacles.contains(&c);
+                if let Some(direction) = Direction::from_symbol(c).filter(|_| !is_obstacle) {
+                    guards.push(GuardLocation {
+                        location: Point {
+                            x: x as isize,
+                            y: y as isize,
+                        },
+                        direction,
+                    });
+                }
+                obstacles.push(is_obstacle.then_some(c));
+            }
+        }
+
+        if guards.is_empty() {
+            return Err(anyhow!("No guard found"));
+        }
+        let mut grid = Self {
+            width,
+            height,
+            obstacles,
+            guards,
+            rules,
+            jumps: Vec::new(),
         };
-        let mut visited = HashSet::new();
-        let mut guard_locations = HashSet::new();
-        visited.insert(guard.location);
-        guard_locations.insert(guard);
-        Self {
-            grid,
-            guard: Some(guard),
-            visited,
-            guard_locations,
-            looped: false,
+        grid.jumps = grid.build_jumps();
+        Ok(grid)
+    }
+
+    fn build_jumps(&self) -> Vec<[Option<Point>; 4]> {
+        let mut jumps = vec![[None; 4]; self.width * self.height];
+        for (d, direction) in DIRECTIONS.into_iter().enumerate() {
+            // Visit cells so that the one ahead is always filled in first
+            let mut cells: Vec<Point> = (0..self.height as isize)
+                .flat_map(|y| (0..self.width as isize).map(move |x| Point { x, y }))
+                .collect();
+            if matches!(direction, Direction::Right | Direction::Down) {
+                cells.reverse();
+            }
+            for location in cells {
+                let ahead = GuardLocation {
+                    location,
+                    direction,
+                }
+                .next();
+                jumps[self.index(&location)][d] = if !self.contains(&ahead) {
+                    None
+                } else if self.is_obstacle(&ahead) {
+                    Some(location)
+                } else {
+                    jumps[self.index(&ahead)][d]
+                };
+            }
         }
+        jumps
+    }
+
+    fn index(&self, point: &Point) -> usize {
+        point.y as usize * self.width + point.x as usize
     }
 
     fn contains(&self, point: &Point) -> bool {
         point.x >= 0
             && point.y >= 0
-            && point.y < self.grid.len() as isize
-            && point.x < self.grid[0].len() as isize
-    }
-
-    fn next_guard(&mut self) {
-        if let Some(mut guard) = self.guard.take() {
-            let next_loc = guard.next();
-            if self.contains(&next_loc) {
-                if self.grid[next_loc.y as usize][next_loc.x as usize] == '#' {
-                    guard.direction = match guard.direction {
-                        Direction::Up => Direction::Right,
-                        Direction::Right => Direction::Down,
-                        Direction::Down => Direction::Left,
-                        Direction::Left => Direction::Up,
-                    };
-                    self.guard = Some(guard);
+            && point.y < self.height as isize
+            && point.x < self.width as isize
+    }
+
+    fn is_obstacle(&self, point: &Point) -> bool {
+        self.obstacles[self.index(point)].is_some()
+    }
+
+    fn single_guard(&self) -> anyhow::Result<GuardLocation> {
+        match self.guards[..] {
+            [guard] => Ok(guard),
+            _ => Err(anyhow!("Loop search needs exactly one guard")),
+        }
+    }
+
+    // `extra` holds cells that are blocked on top of the map's own obstacles
+    fn step(&self, mut guard: GuardLocation, extra: &[Point]) -> Option<GuardLocation> {
+        let mut next_loc = guard.next();
+        if !self.contains(&next_loc) {
+            if !self.rules.wrap {
+                return None;
+            }
+            next_loc = Point {
+                x: next_loc.x.rem_euclid(self.width as isize),
+                y: next_loc.y.rem_euclid(self.height as isize),
+            };
+        }
+        if self.is_obstacle(&next_loc) || extra.contains(&next_loc) {
+            guard.direction = self.rules.turn(guard.direction);
+        } else {
+            guard.location = next_loc;
+        }
+        Some(guard)
+    }
+
+    // Moves all guards at once, each treating the others as obstacles, until
+    // every guard has left or the whole group repeats a state
+    fn lockstep(&self) -> Vec<GuardLocation> {
+        let mut guards: Vec<Option<GuardLocation>> =
+            self.guards.iter().copied().map(Some).collect();
+        let mut seen = HashSet::new();
+        let mut states = Vec::new();
+        while guards.iter().any(Option::is_some) && seen.insert(guards.clone()) {
+            states.extend(guards.iter().flatten());
+            guards = guards
+                .iter()
+                .enumerate()
+                .map(|(i, g)| {
+                    let others: Vec<Point> = guards
+                        .iter()
+                        .enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .filter_map(|(_, g)| g.map(|g| g.location))
+                        .collect();
+                    g.and_then(|g| self.step(g, &others))
+                })
+                .collect();
+        }
+        states
+    }
+
+    // Every state of the guard until it leaves the map or repeats itself,
+    // along with where the repeated loop starts in the path
+    fn patrol_from(
+        &self,
+        start: GuardLocation,
+        extra: &[Point],
+    ) -> (Vec<GuardLocation>, Option<usize>) {
+        let mut seen = HashMap::new();
+        let mut path = Vec::new();
+        let mut guard = Some(start);
+        while let Some(g) = guard {
+            if let Some(&i) = seen.get(&g) {
+                return (path, Some(i));
+            }
+            seen.insert(g, path.len());
+            path.push(g);
+            guard = self.step(g, extra);
+        }
+        (path, None)
+    }
+
+    // Every new obstacle that traps the guard, with the guard's state just
+    // before it would first have walked into that cell
+    fn loop_obstacles(&self) -> anyhow::Result<Vec<(Point, GuardLocation)>> {
+        let guard = self.single_guard()?;
+        let (path, _) = self.patrol_from(guard, &[]);
+        let mut tried = vec![false; self.width * self.height];
+        tried[self.index(&guard.location)] = true;
+        let mut seen = vec![0; self.width * self.height * 4];
+        let mut stamp = 0;
+        let mut obstacles = Vec::new();
+
+        for (before, after) in path.iter().zip(&path[1..]) {
+            let candidate = after.location;
+            if tried[self.index(&candidate)] {
+                continue;
+            }
+            tried[self.index(&candidate)] = true;
+            stamp += 1;
+            if self.loops_with(*before, candidate, &mut seen, stamp) {
+                obstacles.push((candidate, *before));
+            }
+        }
+        Ok(obstacles)
+    }
+
+    fn render(&self, marks: &BTreeMap<Point, BTreeSet<Direction>>, extra: Option<Point>) -> String {
+        let mut out = String::new();
+        for y in 0..self.height as isize {
+            for x in 0..self.width as isize {
+                let point = Point { x, y };
+                let c = if extra == Some(point) {
+                    'O'
+                } else if let Some(c) = self.obstacles[self.index(&point)] {
+                    c as char
+                } else if let Some(g) = self.guards.iter().find(|g| g.location == point) {
+                    g.direction.symbol()
                 } else {
-                    guard.location = next_loc;
-                    self.guard = Some(guard);
-                }
-            } else {
-                self.guard = None;
+                    match marks.get(&point) {
+                        None => '.',
+                        Some(d) => {
+                            let vertical = d.iter().any(Direction::is_vertical);
+                            let horizontal = d.iter().any(|d| !d.is_vertical());
+                            match (vertical, horizontal) {
+                                (true, true) => '+',
+                                (true, false) => '|',
+                                _ => '-',
+                            }
+                        }
+                    }
+                };
+                out.push(c);
             }
+            out.push('\n');
         }
+        out
     }
 
-    fn next(&mut self) {
-        self.next_guard();
-        self.guard.into_iter().for_each(|g| {
-            if !self.guard_locations.contains(&g) {
-                self.guard_locations.insert(g);
-            } else {
-                self.looped = true;
+    // Jumps between turning points, treating `extra` as one more obstacle.
+    // `seen` holds the last `stamp` each turning state was visited with, so
+    // it can be reused across runs without clearing. The jump tables end at
+    // the map's edges, so wrapping maps are walked step by step instead.
+    fn loops_with(
+        &self,
+        mut guard: GuardLocation,
+        extra: Point,
+        seen: &mut [u32],
+        stamp: u32,
+    ) -> bool {
+        if self.rules.wrap {
+            return self.patrol_from(guard, &[extra]).1.is_some();
+        }
+        loop {
+            let d = guard.direction as usize;
+            let mut stop = self.jumps[self.index(&guard.location)][d];
+            let ahead = match guard.direction {
+                Direction::Up => extra.x == guard.location.x && extra.y < guard.location.y,
+                Direction::Down => extra.x == guard.location.x && extra.y > guard.location.y,
+                Direction::Left => extra.y == guard.location.y && extra.x < guard.location.x,
+                Direction::Right => extra.y == guard.location.y && extra.x > guard.location.x,
+            };
+            let distance =
+                |p: &Point| (p.x - guard.location.x).abs() + (p.y - guard.location.y).abs();
+            if ahead && stop.is_none_or(|s| distance(&extra) <= distance(&s)) {
+                // Step back from the new obstacle towards the guard
+                let back = GuardLocation {
+                    location: extra,
+                    direction: guard.direction.reverse(),
+                };
+                stop = Some(back.next());
             }
-        });
-        match self.guard {
-            None => (),
-            Some(ref guard) => {
-                self.visited.insert(guard.location);
+
+            let Some(location) = stop else {
+                return false;
+            };
+            let state = self.index(&location) * 4 + d;
+            if seen[state] == stamp {
+                return true;
             }
+            seen[state] = stamp;
+            guard = GuardLocation {
+                location,
+                direction: self.rules.turn(guard.direction),
+            };
         }
     }
 }
 
-pub fn part_a(input: &Path) -> anyhow::Result<usize> {
-    let mut grid = Grid::new(read_to_string(input)?);
-    while grid.guard.is_some() {
-        grid.next();
+pub fn part_a(input: &Path, rules: &Rules) -> anyhow::Result<usize> {
+    let grid = Grid::new(&read_to_string(input)?, rules)?;
+    let visited: HashSet<Point> = grid.lockstep().iter().map(|g| g.location).collect();
+    Ok(visited.len())
+}
+
+// Only cells on the original path can change it, and each is tried from the
+// state just before the guard first reaches it.
+pub fn part_b(input: &Path, rules: &Rules) -> anyhow::Result<usize> {
+    let grid = Grid::new(&read_to_string(input)?, rules)?;
+    Ok(grid.loop_obstacles()?.len())
+}
+
+fn directions_by_cell(path: &[GuardLocation]) -> BTreeMap<Point, BTreeSet<Direction>> {
+    let mut marks: BTreeMap<Point, BTreeSet<Direction>> = BTreeMap::new();
+    for g in path {
+        marks.entry(g.location).or_default().insert(g.direction);
     }
-    Ok(grid.visited.len())
+    marks
 }
 
-pub fn part_b(input: &Path) -> anyhow::Result<usize> {
-    let input_str = read_to_string(input)?;
-    let mut count = 0;
-    let str_len = input_str.len();
-    for i in 0..str_len {
-        println!("{}/{}", i, str_len);
-        if input_str.chars().nth(i) != Some('.') {
-            continue;
-        }
-        let mut vec_string = input_str.chars().collect::<Vec<char>>();
-        vec_string[i] = '#';
-        let new_str: String = vec_string.iter().collect();
+fn format_cells(marks: &BTreeMap<Point, BTreeSet<Direction>>) -> String {
+    marks
+        .iter()
+        .map(|(p, d)| format!("{},{} {}", p.x, p.y, d.iter().map(|d| d.symbol()).join("")))
+        .join("\n")
+}
 
-        let mut grid = Grid::new(new_str);
-        while grid.guard.is_some() && !grid.looped {
-            grid.next();
-        }
-        if grid.guard.is_some() {
-            count += 1;
+pub fn coverage(input: &Path, rules: &Rules, render: bool) -> anyhow::Result<String> {
+    let grid = Grid::new(&read_to_string(input)?, rules)?;
+    let marks = directions_by_cell(&grid.lockstep());
+    if render {
+        Ok(grid.render(&marks, None))
+    } else {
+        Ok(format_cells(&marks) + "\n")
+    }
+}
+
+pub fn loops(input: &Path, rules: &Rules, render: bool) -> anyhow::Result<String> {
+    let grid = Grid::new(&read_to_string(input)?, rules)?;
+    let mut out = String::new();
+    for (obstacle, before) in grid.loop_obstacles()? {
+        let (path, start) = grid.patrol_from(before, &[obstacle]);
+        let marks = directions_by_cell(&path[start.unwrap_or(0)..]);
+        out.push_str(&format!("obstacle {},{}\n", obstacle.x, obstacle.y));
+        if render {
+            out.push_str(&grid.render(&marks, Some(obstacle)));
+        } else {
+            out.push_str(&format_cells(&marks));
+            out.push('\n');
         }
     }
-    Ok(count)
+    Ok(out)
 }
@@ -1,90 +1,641 @@
+use anyhow::anyhow;
 use itertools::Itertools;
+use num_bigint::BigUint;
+use num_traits::{CheckedAdd, ToPrimitive, Zero};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::str::FromStr;
 
 fn read_to_string(input: &Path) -> anyhow::Result<String> {
     std::fs::read_to_string(input).map_err(Into::into)
 }
 
-struct Equation {
-    target: u64,
-    components: Vec<u64>,
+struct Equation<T = u64> {
+    target: T,
+    components: Vec<T>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum Op {
     Add,
     Mul,
     Concat,
+    Sub,
+    Div,
+    Pow,
+    Xor,
 }
 
-impl Equation {
-    fn new(s: &str) -> Self {
-        let (target, parts) = s.split_once(":").unwrap();
-        let target = target.parse().unwrap();
-        let components = parts
-            .split_whitespace()
-            .map(|x| x.parse().unwrap())
-            .collect();
-        Self { target, components }
-    }
-
-    fn test_op_seq(&self, seq: &[Op]) -> bool {
-        self.target
-            == self.components[1..].iter().zip_eq(seq.iter()).fold(
-                self.components[0],
-                |acc, (a, op)| match op {
-                    Op::Add => acc + a,
-                    Op::Mul => acc * a,
-                    Op::Concat => format!("{}{}", acc, a).parse().unwrap(),
-                },
-            )
-    }
-
-    fn test_all_op_seqs(&self, ops: &[Op]) -> bool {
-        let size = self.components.len() - 1;
-        let combinations = (0..size)
-            .map(|_| ops.iter().cloned())
-            .multi_cartesian_product();
-        for seq in combinations {
-            if self.test_op_seq(&seq) {
-                println!(
-                    "{}: {} works with {:?}",
-                    self.target,
-                    self.components.iter().join(" "),
-                    seq
-                );
-                return true;
+const OPS: [Op; 7] = [
+    Op::Add,
+    Op::Mul,
+    Op::Concat,
+    Op::Sub,
+    Op::Div,
+    Op::Pow,
+    Op::Xor,
+];
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Op::Add => "+",
+            Op::Mul => "*",
+            Op::Concat => "||",
+            Op::Sub => "-",
+            Op::Div => "/",
+            Op::Pow => "^",
+            Op::Xor => "xor",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OpSet(Vec<Op>);
+
+impl FromStr for OpSet {
+    type Err = anyhow::Error;
+
+    // Comma separated operator symbols, e.g. `+,*,||`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let ops: Vec<Op> = s
+            .split(',')
+            .map(|symbol| {
+                OPS.into_iter()
+                    .find(|op| op.to_string() == symbol.trim())
+                    .ok_or(anyhow!("Unknown operator {:?}", symbol))
+            })
+            .try_collect()?;
+        Ok(Self(ops.into_iter().unique_by(|op| *op as u8).collect()))
+    }
+}
+
+// The power of ten that `b` is shifted past when concatenated onto something
+fn magnitude(b: u64) -> Option<u64> {
+    let mut m: u64 = 10;
+    while m <= b {
+        m = m.checked_mul(10)?;
+    }
+    Some(m)
+}
+
+// The whole `n`th root of `target`, if it has one
+fn nth_root(target: u64, n: u64) -> Option<u64> {
+    // Anything above 1 overflows at such a power
+    if n >= 64 {
+        return (target <= 1).then_some(target);
+    }
+    let (mut low, mut high) = (0, target);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        match mid.checked_pow(n as u32) {
+            Some(power) if power <= target => low = mid,
+            _ => high = mid - 1,
+        }
+    }
+    (low.checked_pow(n as u32) == Some(target)).then_some(low)
+}
+
+// Every left-hand side that `op` could turn into the target. Dividing by
+// at most this leaves few enough of them to try one by one.
+const FEW: u32 = 16;
+
+enum Preimage<T> {
+    None,
+    One(T),
+    Few(Vec<T>),
+    Range(RangeInclusive<T>),
+    Any,
+}
+
+impl Op {
+    // Concatenation binds tightest and xor loosest
+    fn precedence(&self) -> u8 {
+        match self {
+            Op::Concat => 4,
+            Op::Pow => 3,
+            Op::Mul | Op::Div => 2,
+            Op::Add | Op::Sub => 1,
+            Op::Xor => 0,
+        }
+    }
+
+    fn apply(&self, lhs: u64, rhs: u64) -> Option<u64> {
+        match self {
+            Op::Add => lhs.checked_add(rhs),
+            Op::Mul => lhs.checked_mul(rhs),
+            Op::Concat => lhs.checked_mul(magnitude(rhs)?)?.checked_add(rhs),
+            Op::Sub => lhs.checked_sub(rhs),
+            Op::Div => lhs.checked_div(rhs),
+            Op::Pow if lhs <= 1 => Some(if rhs == 0 { 1 } else { lhs }),
+            Op::Pow => lhs.checked_pow(rhs.try_into().ok()?),
+            Op::Xor => Some(lhs ^ rhs),
+        }
+    }
+
+    fn undo(&self, target: u64, last: u64) -> Preimage<u64> {
+        let one = |lhs: Option<u64>| lhs.map_or(Preimage::None, Preimage::One);
+        match self {
+            Op::Add => one(target.checked_sub(last)),
+            Op::Mul if last == 0 => match target {
+                0 => Preimage::Any,
+                _ => Preimage::None,
+            },
+            Op::Mul => one(target.is_multiple_of(last).then(|| target / last)),
+            Op::Concat => {
+                one(magnitude(last).and_then(|m| (target % m == last).then(|| target / m)))
             }
+            Op::Sub => one(target.checked_add(last)),
+            Op::Div if last == 0 => Preimage::None,
+            Op::Div => match target.checked_mul(last) {
+                Some(low) if last <= FEW.into() => {
+                    Preimage::Few((low..=low.saturating_add(last - 1)).collect())
+                }
+                Some(low) => Preimage::Range(low..=low.saturating_add(last - 1)),
+                None => Preimage::None,
+            },
+            Op::Pow if last == 0 => match target {
+                1 => Preimage::Any,
+                _ => Preimage::None,
+            },
+            Op::Pow => one(nth_root(target, last)),
+            Op::Xor => one(Some(target ^ last)),
         }
-        false
     }
 }
 
-fn solve(equations: &[Equation], ops: &[Op]) -> u64 {
-    let mut count = 0;
-    let num_eqs = equations.len();
+// The numbers equations are solved over: plain `u64`, or `BigUint` for
+// calibrations that don't fit
+trait Value: Clone + Ord + Hash + FromStr + CheckedAdd + Zero + fmt::Display + Send + Sync {
+    fn apply(op: Op, lhs: &Self, rhs: &Self) -> Option<Self>;
+    fn undo(op: Op, target: &Self, last: &Self) -> Preimage<Self>;
+}
+
+impl Value for u64 {
+    fn apply(op: Op, lhs: &Self, rhs: &Self) -> Option<Self> {
+        op.apply(*lhs, *rhs)
+    }
 
-    for (i, eq) in equations.iter().enumerate() {
-        println!("{} / {}", i, num_eqs);
-        if eq.test_all_op_seqs(ops) {
-            count += eq.target;
+    fn undo(op: Op, target: &Self, last: &Self) -> Preimage<Self> {
+        op.undo(*target, *last)
+    }
+}
+
+// Powers are only worth computing while they stay a sensible size
+const MAX_BITS: u64 = 1 << 16;
+
+fn big_magnitude(b: &BigUint) -> BigUint {
+    BigUint::from(10u32).pow(b.to_string().len() as u32)
+}
+
+impl Value for BigUint {
+    fn apply(op: Op, lhs: &Self, rhs: &Self) -> Option<Self> {
+        match op {
+            Op::Add => Some(lhs + rhs),
+            Op::Mul => Some(lhs * rhs),
+            Op::Concat => Some(lhs * big_magnitude(rhs) + rhs),
+            Op::Sub => (lhs >= rhs).then(|| lhs - rhs),
+            Op::Div => (!rhs.is_zero()).then(|| lhs / rhs),
+            Op::Pow if lhs <= &BigUint::from(1u32) => match rhs.is_zero() {
+                true => Some(BigUint::from(1u32)),
+                false => Some(lhs.clone()),
+            },
+            Op::Pow => {
+                let exponent = rhs.to_u32()?;
+                (lhs.bits().checked_mul(exponent.into())? <= MAX_BITS).then(|| lhs.pow(exponent))
+            }
+            Op::Xor => Some(lhs ^ rhs),
+        }
+    }
+
+    fn undo(op: Op, target: &Self, last: &Self) -> Preimage<Self> {
+        let one = |lhs: Option<Self>| lhs.map_or(Preimage::None, Preimage::One);
+        match op {
+            Op::Add => one((target >= last).then(|| target - last)),
+            Op::Mul if last.is_zero() => match target.is_zero() {
+                true => Preimage::Any,
+                false => Preimage::None,
+            },
+            Op::Mul => one((target % last).is_zero().then(|| target / last)),
+            Op::Concat => {
+                let m = big_magnitude(last);
+                one((&(target % &m) == last).then(|| target / m))
+            }
+            Op::Sub => one(Some(target + last)),
+            Op::Div if last.is_zero() => Preimage::None,
+            Op::Div => {
+                let low = target * last;
+                match last.to_u32().filter(|last| *last <= FEW) {
+                    Some(few) => Preimage::Few((0..few).map(|i| &low + i).collect()),
+                    None => {
+                        let high = &low + last - 1u32;
+                        Preimage::Range(low..=high)
+                    }
+                }
+            }
+            Op::Pow if last.is_zero() => match target == &BigUint::from(1u32) {
+                true => Preimage::Any,
+                false => Preimage::None,
+            },
+            // Only 0 and 1 survive an exponent this large
+            Op::Pow if last.to_u32().is_none() => {
+                one((target <= &BigUint::from(1u32)).then(|| target.clone()))
+            }
+            Op::Pow => {
+                let root = target.nth_root(last.to_u32().unwrap());
+                one((&root.pow(last.to_u32().unwrap()) == target).then_some(root))
+            }
+            Op::Xor => one(Some(target ^ last)),
+        }
+    }
+}
+
+impl<T: Value> Equation<T> {
+    fn new(s: &str, remedy: &str) -> anyhow::Result<Self> {
+        let parse = |x: &str| {
+            let x = x.trim();
+            x.parse()
+                .map_err(|_| match x.bytes().all(|b| b.is_ascii_digit()) {
+                    _ if x.is_empty() => anyhow!("Missing number in {:?}", s),
+                    true => anyhow!("{} does not fit in 64 bits, {}", x, remedy),
+                    false => anyhow!("{:?} is not a number", x),
+                })
+        };
+        let (target, parts) = s.split_once(":").ok_or(anyhow!("Missing `:` in {:?}", s))?;
+        let target = parse(target)?;
+        let components = parts.split_whitespace().map(parse).try_collect()?;
+        Ok(Self { target, components })
+    }
+
+    fn is_solvable(&self, ops: &[Op]) -> bool {
+        solvable(&self.target, &self.components, ops)
+    }
+
+    fn format(&self, seq: &[Op]) -> String {
+        let mut s = self.components[0].to_string();
+        for (op, c) in seq.iter().zip(&self.components[1..]) {
+            s.push_str(&format!(" {} {}", op, c));
         }
+        format!("{} = {}", s, self.target)
+    }
+}
+
+// Works from the last component backwards, so each operator only survives
+// if it could have produced the current target. A division by a large
+// number leaves a whole range of left-hand sides, so those are checked
+// against the values the rest can reach instead of one by one.
+fn solvable<T: Value>(target: &T, components: &[T], ops: &[Op]) -> bool {
+    match components {
+        [] => false,
+        [first] => first == target,
+        [rest @ .., last] => ops.iter().any(|op| match T::undo(*op, target, last) {
+            Preimage::None => false,
+            Preimage::One(lhs) => solvable(&lhs, rest, ops),
+            Preimage::Few(all) => all.iter().any(|lhs| solvable(lhs, rest, ops)),
+            Preimage::Range(range) => reachable(rest, ops).keys().any(|v| range.contains(v)),
+            Preimage::Any => evaluations(rest, ops).1.is_some(),
+        }),
+    }
+}
+
+// How many operator sequences there are, `None` once that overflows, and
+// one of them if there are any
+type Solutions = (Option<u128>, Option<Vec<Op>>);
+
+const NONE: Solutions = (Some(0), None);
+
+fn add(a: Option<u128>, b: Option<u128>) -> Option<u128> {
+    a?.checked_add(b?)
+}
+
+fn merge(total: &mut Solutions, (count, example): Solutions, op: Op) {
+    total.0 = add(total.0, count);
+    if total.1.is_none() {
+        total.1 = example.map(|mut seq| {
+            seq.push(op);
+            seq
+        });
     }
-    count
 }
 
-pub fn part_a(input: &Path) -> anyhow::Result<u64> {
+// Every value the components evaluate to, with the operator sequences
+// reaching it
+fn reachable<T: Value>(components: &[T], ops: &[Op]) -> HashMap<T, Solutions> {
+    let Some((first, rest)) = components.split_first() else {
+        return HashMap::new();
+    };
+    let mut values: HashMap<T, Solutions> =
+        HashMap::from([(first.clone(), (Some(1), Some(Vec::new())))]);
+    for c in rest {
+        let mut next: HashMap<T, Solutions> = HashMap::new();
+        for (value, solutions) in &values {
+            for op in ops {
+                if let Some(v) = T::apply(*op, value, c) {
+                    merge(next.entry(v).or_insert(NONE), solutions.clone(), *op);
+                }
+            }
+        }
+        values = next;
+    }
+    values
+}
+
+fn combine(all: impl IntoIterator<Item = Solutions>) -> Solutions {
+    all.into_iter().fold(NONE, |mut total, (count, example)| {
+        total.0 = add(total.0, count);
+        total.1 = total.1.or(example);
+        total
+    })
+}
+
+// How many operator sequences evaluate at all, without any step failing
+fn evaluations<T: Value>(components: &[T], ops: &[Op]) -> Solutions {
+    combine(reachable(components, ops).into_values())
+}
+
+// Counts every operator sequence that hits the target, keeping one of them
+fn solutions<T: Value>(
+    target: &T,
+    components: &[T],
+    ops: &[Op],
+    memo: &mut HashMap<(usize, T), Solutions>,
+) -> Solutions {
+    let [rest @ .., last] = components else {
+        return NONE;
+    };
+    if rest.is_empty() {
+        return if last == target {
+            (Some(1), Some(Vec::new()))
+        } else {
+            NONE
+        };
+    }
+    if let Some(s) = memo.get(&(components.len(), target.clone())) {
+        return s.clone();
+    }
+
+    let mut total = NONE;
+    for op in ops {
+        match T::undo(*op, target, last) {
+            Preimage::None => (),
+            Preimage::One(lhs) => merge(&mut total, solutions(&lhs, rest, ops, memo), *op),
+            Preimage::Few(all) => {
+                for lhs in &all {
+                    merge(&mut total, solutions(lhs, rest, ops, memo), *op);
+                }
+            }
+            Preimage::Range(range) => {
+                let within = reachable(rest, ops)
+                    .into_iter()
+                    .filter(|(v, _)| range.contains(v))
+                    .map(|(_, s)| s);
+                merge(&mut total, combine(within), *op);
+            }
+            Preimage::Any => merge(&mut total, evaluations(rest, ops), *op),
+        }
+    }
+    memo.insert((components.len(), target.clone()), total.clone());
+    total
+}
+
+#[derive(Copy, Clone)]
+enum Semantics {
+    LeftToRight,
+    Precedence,
+    Parenthesized,
+}
+
+impl fmt::Display for Semantics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Semantics::LeftToRight => "left-to-right",
+            Semantics::Precedence => "precedence",
+            Semantics::Parenthesized => "parenthesized",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// With only growing operators and no zeros, no partial value can shrink
+// again, so anything already past the target can be dropped
+fn is_monotone(components: &[u64], ops: &[Op]) -> bool {
+    components.iter().all(|c| *c > 0)
+        && ops
+            .iter()
+            .all(|op| matches!(op, Op::Add | Op::Mul | Op::Concat))
+}
+
+// Applies the pending operators on `stack` to `value` for as long as they
+// bind at least as tightly as `precedence`; `^` is right associative
+fn collapse(stack: &mut Vec<(u64, Op)>, mut value: u64, precedence: Option<Op>) -> Option<u64> {
+    while let Some(&(lhs, op)) = stack.last() {
+        let binds = match precedence {
+            None => true,
+            Some(next) if next == Op::Pow => op.precedence() > next.precedence(),
+            Some(next) => op.precedence() >= next.precedence(),
+        };
+        if !binds {
+            break;
+        }
+        stack.pop();
+        value = op.apply(lhs, value)?;
+    }
+    Some(value)
+}
+
+fn solvable_with_precedence(
+    eq: &Equation,
+    ops: &[Op],
+    stack: &mut Vec<(u64, Op)>,
+    value: u64,
+    next: usize,
+    prune: bool,
+) -> bool {
+    if prune && collapse(&mut stack.clone(), value, None).is_none_or(|v| v > eq.target) {
+        return false;
+    }
+    let Some(&c) = eq.components.get(next) else {
+        return collapse(stack, value, None) == Some(eq.target);
+    };
+    ops.iter().any(|&op| {
+        let mut stack = stack.clone();
+        collapse(&mut stack, value, Some(op)).is_some_and(|value| {
+            stack.push((value, op));
+            solvable_with_precedence(eq, ops, &mut stack, c, next + 1, prune)
+        })
+    })
+}
+
+// Every value each run of components can take under some bracketing
+fn solvable_parenthesized(eq: &Equation, ops: &[Op]) -> bool {
+    let n = eq.components.len();
+    let prune = is_monotone(&eq.components, ops);
+    let mut values: HashMap<(usize, usize), HashSet<u64>> = HashMap::new();
+    for (i, c) in eq.components.iter().enumerate() {
+        values.insert((i, i), HashSet::from([*c]));
+    }
+    for len in 2..=n {
+        for i in 0..=n - len {
+            let j = i + len - 1;
+            let mut here = HashSet::new();
+            for split in i..j {
+                for a in &values[&(i, split)] {
+                    for b in &values[&(split + 1, j)] {
+                        here.extend(
+                            ops.iter()
+                                .filter_map(|op| op.apply(*a, *b))
+                                .filter(|v| !prune || *v <= eq.target),
+                        );
+                    }
+                }
+            }
+            values.insert((i, j), here);
+        }
+    }
+    n > 0 && values[&(0, n - 1)].contains(&eq.target)
+}
+
+impl Equation {
+    fn is_solvable_with(&self, ops: &[Op], semantics: Semantics) -> bool {
+        match semantics {
+            Semantics::LeftToRight => self.is_solvable(ops),
+            Semantics::Precedence => match self.components.split_first() {
+                None => false,
+                Some((first, _)) => solvable_with_precedence(
+                    self,
+                    ops,
+                    &mut Vec::new(),
+                    *first,
+                    1,
+                    is_monotone(&self.components, ops),
+                ),
+            },
+            Semantics::Parenthesized => solvable_parenthesized(self, ops),
+        }
+    }
+}
+
+// What to do about numbers that outgrow 64 bits, which depends on the mode
+const TRY_BIG: &str = "try --big";
+const ONLY_64_BITS: &str = "day 7 semantics only supports 64 bits";
+
+fn parse<T: Value>(input: &str, remedy: &str) -> anyhow::Result<Vec<Equation<T>>> {
+    input
+        .lines()
+        .map(|line| Equation::new(line, remedy))
+        .try_collect()
+}
+
+fn total<'a, T: Value + 'a>(
+    targets: impl IntoIterator<Item = &'a T>,
+    remedy: &str,
+) -> anyhow::Result<T> {
+    targets
+        .into_iter()
+        .try_fold(T::zero(), |sum, t| sum.checked_add(t))
+        .ok_or_else(|| anyhow!("Total calibration result overflows 64 bits, {}", remedy))
+}
+
+fn solve<T: Value>(input: &str, ops: &[Op]) -> anyhow::Result<String> {
+    let equations: Vec<Equation<T>> = parse(input, TRY_BIG)?;
+    let solvable: Vec<&T> = equations
+        .par_iter()
+        .filter(|eq| eq.is_solvable(ops))
+        .map(|eq| &eq.target)
+        .collect();
+    Ok(total(solvable, TRY_BIG)?.to_string())
+}
+
+pub fn part_a(input: &Path, big: bool) -> anyhow::Result<String> {
     let input = read_to_string(input)?;
-    let equations: Vec<Equation> = input.lines().map(Equation::new).collect();
     let ops = [Op::Add, Op::Mul];
 
-    Ok(solve(&equations, &ops))
+    match big {
+        true => solve::<BigUint>(&input, &ops),
+        false => solve::<u64>(&input, &ops),
+    }
 }
 
-pub fn part_b(input: &Path) -> anyhow::Result<u64> {
+pub fn part_b(input: &Path, big: bool) -> anyhow::Result<String> {
     let input = read_to_string(input)?;
-    let equations: Vec<Equation> = input.lines().map(Equation::new).collect();
     let ops = [Op::Add, Op::Mul, Op::Concat];
 
-    Ok(solve(&equations, &ops))
+    match big {
+        true => solve::<BigUint>(&input, &ops),
+        false => solve::<u64>(&input, &ops),
+    }
+}
+
+fn calibrate<T: Value>(input: &str, ops: &[Op]) -> anyhow::Result<String> {
+    let equations: Vec<Equation<T>> = parse(input, TRY_BIG)?;
+    let lines: Vec<(String, Option<&T>)> = equations
+        .par_iter()
+        .map(|eq| {
+            let (count, example) = solutions(&eq.target, &eq.components, ops, &mut HashMap::new());
+            let count = count.ok_or_else(|| {
+                anyhow!("Number of assignments for {} overflows 128 bits", eq.target)
+            })?;
+            Ok(match example {
+                Some(seq) => (
+                    format!("{}, {} assignments", eq.format(&seq), count),
+                    Some(&eq.target),
+                ),
+                None => (
+                    format!(
+                        "{}: {}, 0 assignments",
+                        eq.target,
+                        eq.components.iter().join(" ")
+                    ),
+                    None,
+                ),
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut out = String::new();
+    for (line, _) in &lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+    let total = total(lines.iter().filter_map(|(_, t)| *t), TRY_BIG)?;
+    out.push_str(&format!("total {}\n", total));
+    Ok(out)
+}
+
+pub fn expressions(input: &Path, ops: &OpSet, big: bool) -> anyhow::Result<String> {
+    let input = read_to_string(input)?;
+    match big {
+        true => calibrate::<BigUint>(&input, &ops.0),
+        false => calibrate::<u64>(&input, &ops.0),
+    }
+}
+
+pub fn semantics(input: &Path, ops: &OpSet, parenthesize: bool) -> anyhow::Result<String> {
+    let input = read_to_string(input)?;
+    let equations: Vec<Equation> = parse(&input, ONLY_64_BITS)?;
+    let mut out = String::new();
+    let mut all = vec![Semantics::LeftToRight, Semantics::Precedence];
+    if parenthesize {
+        all.push(Semantics::Parenthesized);
+    }
+    for semantics in all {
+        let solvable: Vec<u64> = equations
+            .par_iter()
+            .filter(|eq| eq.is_solvable_with(&ops.0, semantics))
+            .map(|eq| eq.target)
+            .collect();
+        out.push_str(&format!(
+            "{}: {} of {} solvable, total {}\n",
+            semantics,
+            solvable.len(),
+            equations.len(),
+            total(&solvable, ONLY_64_BITS)?
+        ));
+    }
+    Ok(out)
 }
@@ -1,130 +1,316 @@
+use anyhow::anyhow;
 use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
-use std::iter::repeat;
 use std::ops::{Add, Sub};
 use std::path::Path;
+use std::str::FromStr;
 
 fn read_to_string(input: &Path) -> anyhow::Result<String> {
     std::fs::read_to_string(input).map_err(Into::into)
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-struct Point {
-    x: i32,
-    y: i32,
-}
+struct Point<const N: usize>([i32; N]);
 
-impl Add for Point {
+impl<const N: usize> Add for Point<N> {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
-        Self {
-            x: self.x + other.x,
-            y: self.y + other.y,
-        }
+        Self(std::array::from_fn(|i| self.0[i] + other.0[i]))
     }
 }
 
-impl Sub for Point {
+impl<const N: usize> Sub for Point<N> {
     type Output = Self;
 
     fn sub(self, other: Self) -> Self {
-        self + Point {
-            x: -other.x,
-            y: -other.y,
+        Self(std::array::from_fn(|i| self.0[i] - other.0[i]))
+    }
+}
+
+impl<const N: usize> Point<N> {
+    // `self * num / den`, if that lands exactly on a lattice point
+    fn scale(self, num: i32, den: i32) -> Option<Self> {
+        self.0
+            .iter()
+            .all(|c| (c * num) % den == 0)
+            .then(|| Self(self.0.map(|c| c * num / den)))
+    }
+}
+
+// The half-open box antinodes have to fall within, `min..max` on each axis
+#[derive(Debug, Copy, Clone)]
+struct Volume<const N: usize> {
+    min: Point<N>,
+    max: Point<N>,
+}
+
+impl<const N: usize> Volume<N> {
+    fn contains(&self, p: Point<N>) -> bool {
+        (0..N).all(|i| p.0[i] >= self.min.0[i] && p.0[i] < self.max.0[i])
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bounds(Vec<(i32, i32)>);
+
+impl FromStr for Bounds {
+    type Err = anyhow::Error;
+
+    // One extent per axis, either a size from zero or a `min..max` range,
+    // e.g. `12,12,-3..9`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let axes = s
+            .split(',')
+            .map(|axis| {
+                let axis = axis.trim();
+                let (min, max) = axis.split_once("..").unwrap_or(("0", axis));
+                match (min.parse(), max.parse()) {
+                    (Ok(min), Ok(max)) if min < max => Ok((min, max)),
+                    _ => Err(anyhow!("Invalid extent {:?}", axis)),
+                }
+            })
+            .try_collect()?;
+        Ok(Self(axes))
+    }
+}
+
+impl Bounds {
+    fn volume<const N: usize>(&self) -> Volume<N> {
+        Volume {
+            min: Point(std::array::from_fn(|i| self.0[i].0)),
+            max: Point(std::array::from_fn(|i| self.0[i].1)),
         }
     }
 }
 
-type Antennas = HashMap<char, Vec<Point>>;
+type Antennas<const N: usize> = HashMap<char, Vec<Point<N>>>;
 
-fn parse_antennas(s: String) -> Antennas {
+fn parse_antennas(s: &str) -> (Antennas<2>, Volume<2>) {
     let mut antennas = Antennas::new();
     for (y, line) in s.lines().enumerate() {
         for (x, c) in line.chars().enumerate() {
             if c != '.' {
-                antennas.entry(c).or_default().push(Point {
-                    x: x as i32,
-                    y: y as i32,
-                });
+                antennas
+                    .entry(c)
+                    .or_default()
+                    .push(Point([x as i32, y as i32]));
             }
         }
     }
-    antennas
+    let volume = Volume {
+        min: Point([0, 0]),
+        max: Point([
+            s.lines().next().unwrap().len() as i32,
+            s.lines().count() as i32,
+        ]),
+    };
+    (antennas, volume)
 }
 
-fn find_antinodes(a: Point, b: Point) -> [Point; 2] {
+// One antenna per line, its frequency then its coordinates, e.g. `a 3,4,7`
+fn parse_coordinates<const N: usize>(s: &str) -> anyhow::Result<Antennas<N>> {
+    let mut antennas = Antennas::new();
+    for line in s.lines().filter(|line| !line.trim().is_empty()) {
+        let invalid = || anyhow!("Expected a frequency and {} coordinates: {:?}", N, line);
+        let (frequency, coordinates) = line.trim().split_once(' ').ok_or_else(invalid)?;
+        let frequency = frequency.chars().exactly_one().map_err(|_| invalid())?;
+        let coordinates: Vec<i32> = coordinates
+            .split(',')
+            .map(|c| c.trim().parse())
+            .try_collect()
+            .map_err(|_| invalid())?;
+        let point = Point(coordinates.try_into().map_err(|_| invalid())?);
+        antennas.entry(frequency).or_default().push(point);
+    }
+    Ok(antennas)
+}
+
+fn find_antinodes<const N: usize>(a: Point<N>, b: Point<N>) -> [Point<N>; 2] {
     let d = b - a;
     [b + d, a - d]
 }
 
-fn find_all_antinodes(a: Point, b: Point, size: Point) -> Vec<Point> {
-    let mut antinodes = vec![a, b];
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// The steps `t` with `low <= t * step <= high`, for a nonzero step
+fn steps_within(low: i64, high: i64, step: i64) -> (i64, i64) {
+    let (low, high, step) = match step > 0 {
+        true => (low, high, step),
+        false => (-high, -low, -step),
+    };
+    (-(-low).div_euclid(step), high.div_euclid(step))
+}
+
+// Steps by the smallest lattice vector along the line, so points between
+// the antennas and between the raw repeats are found too. The antennas may
+// be outside the volume, so the steps landing inside it are worked out per
+// axis rather than walked out to from an antenna.
+fn find_all_antinodes<const N: usize>(
+    a: Point<N>,
+    b: Point<N>,
+    volume: &Volume<N>,
+) -> Vec<Point<N>> {
     let d = b - a;
-    let increasing = repeat(d).scan(b, |p, d| {
-        *p = *p + d;
-        if in_bounds(*p, size) {
-            Some(*p)
-        } else {
-            None
-        }
-    });
-    let decreasing = repeat(d).scan(a, |p, d| {
-        *p = *p - d;
-        if in_bounds(*p, size) {
-            Some(*p)
-        } else {
-            None
+    let step = d.scale(1, d.0.into_iter().fold(0, gcd).max(1)).unwrap();
+    if step == Point([0; N]) {
+        return vec![a];
+    }
+    let (mut first, mut last) = (i64::MIN, i64::MAX);
+    for i in 0..N {
+        let low = volume.min.0[i] as i64 - a.0[i] as i64;
+        let high = volume.max.0[i] as i64 - 1 - a.0[i] as i64;
+        if step.0[i] == 0 {
+            if low > 0 || high < 0 {
+                return Vec::new();
+            }
+            continue;
         }
-    });
-    antinodes.extend(increasing);
-    antinodes.extend(decreasing);
-    antinodes
+        let (from, to) = steps_within(low, high, step.0[i] as i64);
+        first = first.max(from);
+        last = last.min(to);
+    }
+    (first..=last)
+        .map(|t| {
+            Point(std::array::from_fn(|i| {
+                (a.0[i] as i64 + t * step.0[i] as i64) as i32
+            }))
+        })
+        .collect()
 }
 
-fn in_bounds(p: Point, size: Point) -> bool {
-    p.x >= 0 && p.x < size.x && p.y >= 0 && p.y < size.y
+#[derive(Debug, Copy, Clone)]
+struct Ratio {
+    num: i32,
+    den: i32,
 }
 
-pub fn part_a(input: &Path) -> anyhow::Result<usize> {
-    let s = read_to_string(input)?;
-    let size = Point {
-        x: s.lines().next().unwrap().len() as i32,
-        y: s.lines().count() as i32,
-    };
-    let mut antinodes = HashSet::new();
+#[derive(Clone, Debug)]
+pub struct Ratios(Vec<Ratio>);
 
-    let antennas = parse_antennas(s);
-    for points in antennas.values() {
-        for (a, b) in points.iter().tuple_combinations() {
-            let antenna_antinodes = find_antinodes(*a, *b);
-            for antinode in &antenna_antinodes {
-                if in_bounds(*antinode, size) {
-                    antinodes.insert(*antinode);
+impl FromStr for Ratios {
+    type Err = anyhow::Error;
+
+    // Comma separated distance ratios, e.g. `2,3/2`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let ratios = s
+            .split(',')
+            .map(|ratio| {
+                let (num, den) = ratio.trim().split_once('/').unwrap_or((ratio.trim(), "1"));
+                match (num.parse(), den.parse()) {
+                    (Ok(num), Ok(den)) if num > 0 && den > 0 => Ok(Ratio { num, den }),
+                    _ => Err(anyhow!("Invalid ratio {:?}", ratio)),
                 }
-            }
+            })
+            .try_collect()?;
+        Ok(Self(ratios))
+    }
+}
+
+impl Ratio {
+    // Where along `a + t * (b - a)` one antenna is `num / den` times as far
+    // away as the other, both outside and between the pair
+    fn positions(&self) -> Vec<Ratio> {
+        let (p, q) = (self.num, self.den);
+        let mut positions = vec![Ratio { num: p, den: p + q }, Ratio { num: q, den: p + q }];
+        if p != q {
+            positions.push(Ratio { num: p, den: p - q });
+            positions.push(Ratio {
+                num: -q,
+                den: p - q,
+            });
         }
+        positions
     }
-    Ok(antinodes.len())
 }
-pub fn part_b(input: &Path) -> anyhow::Result<usize> {
-    let s = read_to_string(input)?;
-    let size = Point {
-        x: s.lines().next().unwrap().len() as i32,
-        y: s.lines().count() as i32,
-    };
-    let mut antinodes = HashSet::new();
 
-    let antennas = parse_antennas(s);
+// Only positions that land exactly on a lattice point count
+fn find_ratio_antinodes<const N: usize>(
+    a: Point<N>,
+    b: Point<N>,
+    ratios: &Ratios,
+) -> Vec<Point<N>> {
+    let d = b - a;
+    ratios
+        .0
+        .iter()
+        .flat_map(Ratio::positions)
+        .filter_map(|t| d.scale(t.num, t.den))
+        .map(|offset| a + offset)
+        .collect()
+}
+
+#[derive(Copy, Clone)]
+enum Rule<'a> {
+    Pair,
+    Harmonics,
+    Ratios(&'a Ratios),
+}
+
+impl Rule<'_> {
+    fn find<const N: usize>(&self, a: Point<N>, b: Point<N>, volume: &Volume<N>) -> Vec<Point<N>> {
+        match self {
+            Rule::Pair => find_antinodes(a, b).to_vec(),
+            Rule::Harmonics => find_all_antinodes(a, b, volume),
+            Rule::Ratios(ratios) => find_ratio_antinodes(a, b, ratios),
+        }
+    }
+}
+
+fn count_antinodes<const N: usize>(
+    antennas: &Antennas<N>,
+    volume: &Volume<N>,
+    rule: Rule,
+) -> usize {
+    let mut antinodes = HashSet::new();
     for points in antennas.values() {
         for (a, b) in points.iter().tuple_combinations() {
-            let antenna_antinodes = find_all_antinodes(*a, *b, size);
-            for antinode in &antenna_antinodes {
-                if in_bounds(*antinode, size) {
-                    antinodes.insert(*antinode);
+            for antinode in rule.find(*a, *b, volume) {
+                if volume.contains(antinode) {
+                    antinodes.insert(antinode);
                 }
             }
         }
     }
-    Ok(antinodes.len())
+    antinodes.len()
+}
+
+fn count_in<const N: usize>(s: &str, bounds: &Bounds, rule: Rule) -> anyhow::Result<usize> {
+    let antennas = parse_coordinates::<N>(s)?;
+    Ok(count_antinodes(&antennas, &bounds.volume(), rule))
+}
+
+// A character grid unless a box is given, in which case the input is a
+// coordinate list with as many axes as the box
+fn count(input: &Path, bounds: Option<&Bounds>, rule: Rule) -> anyhow::Result<usize> {
+    let s = read_to_string(input)?;
+    match bounds.map(|bounds| (bounds, bounds.0.len())) {
+        None => {
+            let (antennas, volume) = parse_antennas(&s);
+            Ok(count_antinodes(&antennas, &volume, rule))
+        }
+        Some((bounds, 1)) => count_in::<1>(&s, bounds, rule),
+        Some((bounds, 2)) => count_in::<2>(&s, bounds, rule),
+        Some((bounds, 3)) => count_in::<3>(&s, bounds, rule),
+        Some((bounds, 4)) => count_in::<4>(&s, bounds, rule),
+        Some((_, n)) => Err(anyhow!("{}-dimensional maps aren't supported", n)),
+    }
+}
+
+pub fn part_a(input: &Path, bounds: Option<&Bounds>) -> anyhow::Result<usize> {
+    count(input, bounds, Rule::Pair)
+}
+
+pub fn part_b(input: &Path, bounds: Option<&Bounds>) -> anyhow::Result<usize> {
+    count(input, bounds, Rule::Harmonics)
+}
+
+pub fn ratios(input: &Path, ratios: &Ratios, bounds: Option<&Bounds>) -> anyhow::Result<usize> {
+    count(input, bounds, Rule::Ratios(ratios))
 }
@@ -1,106 +1,577 @@
-use std::collections::VecDeque;
+use anyhow::anyhow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::path::Path;
+use std::time::Instant;
+
+// Rendering prints every block, so it is only for small disks
+const MAX_RENDERED_BLOCKS: usize = 1 << 20;
 
 fn read_to_string(input: &Path) -> anyhow::Result<String> {
     std::fs::read_to_string(input).map_err(Into::into)
 }
 
+// A contiguous run of blocks
+#[derive(Debug, Copy, Clone)]
+struct Extent {
+    start: usize,
+    len: usize,
+}
+
+impl Extent {
+    fn end(&self) -> usize {
+        self.start + self.len
+    }
+
+    // Wide enough for any extent of a disk whose length fits in a usize
+    fn sum_range(&self) -> u128 {
+        let (start, len) = (self.start as u128, self.len as u128);
+        start * len + len * len.saturating_sub(1) / 2
+    }
+}
+
+// Block compaction can split a file across several extents
 #[derive(Debug)]
 struct File {
     id: usize,
-    size: u32,
+    extents: Vec<Extent>,
 }
 
 impl File {
-    fn new(id: usize, size: u32) -> Self {
-        Self { id, size }
+    fn checksum(&self) -> Option<u128> {
+        self.extents.iter().try_fold(0u128, |sum, e| {
+            sum.checked_add((self.id as u128).checked_mul(e.sum_range())?)
+        })
     }
 }
 
-struct Segments {
-    files: VecDeque<File>,
-    empty_space: VecDeque<u32>,
+#[derive(Debug, Default)]
+struct Moves {
+    count: usize,
+    bytes: usize,
 }
 
-fn sum_range(start: usize, len: usize) -> usize {
-    if len == 0 {
-        return 0;
+impl Moves {
+    fn add(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes;
     }
-    start * len + ((len - 1) * len / 2)
 }
 
-impl Segments {
-    fn new(s: String) -> Self {
-        let mut files = VecDeque::new();
-        let mut empty_space = VecDeque::new();
-        for i in (0..s.len()).step_by(2) {
-            files.push_back(File::new(
-                i / 2,
-                s.chars().nth(i).unwrap().to_digit(10).unwrap(),
-            ));
-            if i + 1 < s.len() {
-                empty_space.push_back(s.chars().nth(i + 1).unwrap().to_digit(10).unwrap());
+#[derive(Copy, Clone)]
+enum Fit {
+    First,
+    Best,
+    Worst,
+}
+
+// The free extents by start, and the starts of those of each length. Sets
+// rather than heaps, so that a vacated extent can absorb its neighbours.
+//
+// Files only ever fill the front of an extent, so no two extents start in
+// the same file or free region of the original layout. A tree over those
+// regions holds the longest extent starting below each node, which finds
+// the leftmost extent long enough for a file in logarithmic time whatever
+// the lengths are.
+struct FreeSpans {
+    by_start: BTreeMap<usize, usize>,
+    by_len: BTreeMap<usize, BTreeSet<usize>>,
+    regions: Vec<usize>,
+    longest: Vec<usize>,
+}
+
+impl FreeSpans {
+    fn new(files: &[File], free: &[Extent]) -> Self {
+        let mut regions: Vec<usize> = files
+            .iter()
+            .flat_map(|file| &file.extents)
+            .chain(free)
+            .map(|extent| extent.start)
+            .collect();
+        regions.sort_unstable();
+        regions.dedup();
+        let mut spans = Self {
+            by_start: BTreeMap::new(),
+            by_len: BTreeMap::new(),
+            longest: vec![0; 2 * regions.len().next_power_of_two()],
+            regions,
+        };
+        for space in free {
+            spans.insert(*space);
+        }
+        spans
+    }
+
+    fn set_longest(&mut self, start: usize, len: usize) {
+        let leaves = self.longest.len() / 2;
+        let mut node = leaves + self.regions.partition_point(|r| *r <= start) - 1;
+        self.longest[node] = len;
+        while node > 1 {
+            node /= 2;
+            self.longest[node] = self.longest[2 * node].max(self.longest[2 * node + 1]);
+        }
+    }
+
+    fn leftmost(&self, len: usize) -> Option<Extent> {
+        let leaves = self.longest.len() / 2;
+        if self.longest.get(1).is_none_or(|longest| *longest < len) {
+            return None;
+        }
+        let mut node = 1;
+        while node < leaves {
+            node = match self.longest[2 * node] >= len {
+                true => 2 * node,
+                false => 2 * node + 1,
+            };
+        }
+        let (&start, &len) = self.by_start.range(self.regions[node - leaves]..).next()?;
+        Some(Extent { start, len })
+    }
+
+    fn insert(&mut self, mut space: Extent) {
+        if space.len == 0 {
+            return;
+        }
+        let before = self.by_start.range(..space.start).next_back();
+        if let Some((&start, &len)) = before.filter(|(start, len)| *start + *len == space.start) {
+            self.remove(Extent { start, len });
+            space = Extent {
+                start,
+                len: len + space.len,
+            };
+        }
+        if let Some(&len) = self.by_start.get(&space.end()) {
+            self.remove(Extent {
+                start: space.end(),
+                len,
+            });
+            space.len += len;
+        }
+        self.by_start.insert(space.start, space.len);
+        self.by_len
+            .entry(space.len)
+            .or_default()
+            .insert(space.start);
+        self.set_longest(space.start, space.len);
+    }
+
+    fn remove(&mut self, space: Extent) {
+        self.by_start.remove(&space.start);
+        let starts = self.by_len.get_mut(&space.len).unwrap();
+        starts.remove(&space.start);
+        if starts.is_empty() {
+            self.by_len.remove(&space.len);
+        }
+        self.set_longest(space.start, 0);
+    }
+
+    // Takes out every extent starting at or after `start`
+    fn split_off(&mut self, start: usize) -> Vec<Extent> {
+        let mut after = Vec::new();
+        while let Some((&from, &len)) = self.by_start.range(start..).next() {
+            let space = Extent { start: from, len };
+            self.remove(space);
+            after.push(space);
+        }
+        after
+    }
+
+    // Best and worst fit look at the shortest and longest extents of all,
+    // so they rely on everything past `before` having been split off
+    fn find(&self, fit: Fit, len: usize, before: usize) -> Option<Extent> {
+        let first_of = |(len, starts): (&usize, &BTreeSet<usize>)| Extent {
+            start: *starts.first().unwrap(),
+            len: *len,
+        };
+        let space = match fit {
+            Fit::First => self.leftmost(len),
+            Fit::Best => self.by_len.range(len..).next().map(first_of),
+            Fit::Worst => self.by_len.range(len..).next_back().map(first_of),
+        };
+        space.filter(|space| space.start < before)
+    }
+
+    fn into_extents(self) -> Vec<Extent> {
+        self.by_start
+            .into_iter()
+            .map(|(start, len)| Extent { start, len })
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Policy {
+    Blocks,
+    FirstFit,
+    BestFit,
+    WorstFit,
+    OrderPreserving,
+    MoveToFront,
+}
+
+const POLICIES: [Policy; 6] = [
+    Policy::Blocks,
+    Policy::FirstFit,
+    Policy::BestFit,
+    Policy::WorstFit,
+    Policy::OrderPreserving,
+    Policy::MoveToFront,
+];
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Policy::Blocks => "blocks",
+            Policy::FirstFit => "first-fit",
+            Policy::BestFit => "best-fit",
+            Policy::WorstFit => "worst-fit",
+            Policy::OrderPreserving => "order-preserving",
+            Policy::MoveToFront => "move-to-front",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Policy {
+    fn compact(&self, disk: &mut DiskMap) -> Moves {
+        match self {
+            Policy::Blocks => disk.compact_blocks(),
+            Policy::FirstFit => disk.compact_files(Fit::First, false),
+            Policy::BestFit => disk.compact_files(Fit::Best, false),
+            Policy::WorstFit => disk.compact_files(Fit::Worst, false),
+            Policy::OrderPreserving => disk.compact_in_order(),
+            Policy::MoveToFront => disk.compact_files(Fit::First, true),
+        }
+    }
+}
+
+struct DiskMap {
+    files: Vec<File>,
+    free: Vec<Extent>,
+    len: usize,
+}
+
+impl DiskMap {
+    // Alternating file and free lengths, starting with a file
+    fn new(lengths: &[usize]) -> anyhow::Result<Self> {
+        let mut files = Vec::new();
+        let mut free: Vec<Extent> = Vec::new();
+        let mut start = 0;
+        for (i, len) in lengths.iter().enumerate() {
+            let extent = Extent { start, len: *len };
+            if i % 2 == 0 {
+                files.push(File {
+                    id: i / 2,
+                    extents: vec![extent],
+                });
+            } else if extent.len > 0 {
+                // An empty file in between leaves the two spans touching
+                match free.last_mut() {
+                    Some(last) if last.end() == start => last.len += extent.len,
+                    _ => free.push(extent),
+                }
             }
+            start = start
+                .checked_add(*len)
+                .ok_or_else(|| anyhow!("Disk map is longer than {} blocks", usize::MAX))?;
         }
-        Self { files, empty_space }
-    }
-
-    fn compute_checksum(&mut self) -> anyhow::Result<usize> {
-        let mut checksum = 0;
-        let mut curr_segment = 0;
-        let mut final_segments = String::new();
-        while !self.files.is_empty() {
-            let curr_file = self
-                .files
-                .pop_front()
-                .ok_or(anyhow::anyhow!("No files left"))?;
-            final_segments.push_str(
-                curr_file
-                    .id
-                    .to_string()
-                    .repeat(curr_file.size as usize)
-                    .as_str(),
-            );
-
-            checksum += curr_file.id * sum_range(curr_segment, curr_file.size as usize);
-            curr_segment += curr_file.size as usize;
-
-            let next_empty_space = self
-                .empty_space
-                .pop_front()
-                .ok_or(anyhow::anyhow!("No empty space left"))?;
-            if next_empty_space == 0 {
+        Ok(Self {
+            files,
+            free,
+            len: start,
+        })
+    }
+
+    // The ID stored in every block, `None` for free ones
+    fn blocks(&self) -> Vec<Option<usize>> {
+        let mut blocks = vec![None; self.len];
+        for file in &self.files {
+            for extent in &file.extents {
+                blocks[extent.start..extent.end()].fill(Some(file.id));
+            }
+        }
+        blocks
+    }
+
+    // Moves single blocks from the end of the disk into the first free
+    // block. Runs of blocks move together, the tail of the last file going
+    // into the leftmost free extent, whichever of the two is longer split.
+    fn compact_blocks(&mut self) -> Moves {
+        let mut moves = Moves::default();
+        let mut spaces = self.free.clone().into_iter();
+        let mut space = spaces.next();
+        for file in self.files.iter_mut().rev() {
+            let mut tail = file.extents[0];
+            let mut extents = Vec::new();
+            while let Some(free) = space
+                .as_mut()
+                .filter(|free| tail.len > 0 && free.start < tail.start)
+            {
+                let len = free.len.min(tail.len);
+                extents.push(Extent {
+                    start: free.start,
+                    len,
+                });
+                tail.len -= len;
+                free.start += len;
+                free.len -= len;
+                if free.len == 0 {
+                    space = spaces.next();
+                }
+                // Every block is a move of its own
+                moves.count += len;
+                moves.bytes += len;
+            }
+            match extents.last_mut() {
+                Some(last) if last.end() == tail.start => last.len += tail.len,
+                Some(_) if tail.len == 0 => (),
+                _ => extents.push(tail),
+            }
+            file.extents = extents;
+        }
+        self.free_uncovered();
+        moves
+    }
+
+    // Whatever no file covers, in order
+    fn free_uncovered(&mut self) {
+        let mut used: Vec<Extent> = self
+            .files
+            .iter()
+            .flat_map(|file| &file.extents)
+            .filter(|extent| extent.len > 0)
+            .copied()
+            .collect();
+        used.sort_by_key(|extent| extent.start);
+        self.free.clear();
+        let mut end = 0;
+        for extent in used {
+            if extent.start > end {
+                self.free.push(Extent {
+                    start: end,
+                    len: extent.start - end,
+                });
+            }
+            end = extent.end();
+        }
+        if end < self.len {
+            self.free.push(Extent {
+                start: end,
+                len: self.len - end,
+            });
+        }
+    }
+
+    // Moves each file at most once into a free extent left of it that holds
+    // all of it, the highest ID first unless `front_first`. Each file costs a
+    // logarithmic number of steps in the number of free extents.
+    fn compact_files(&mut self, fit: Fit, front_first: bool) -> Moves {
+        let mut spans = FreeSpans::new(&self.files, &self.free);
+        let mut moves = Moves::default();
+        let mut passed = Vec::new();
+        let mut order: Vec<usize> = (0..self.files.len()).collect();
+        if !front_first {
+            order.reverse();
+        }
+        for id in order {
+            let extent = &mut self.files[id].extents[0];
+            if extent.len == 0 {
                 continue;
             }
-            if self.files.is_empty() {
-                break;
+            // Working from the back, every file still to move is left of this
+            // one, so neither the space right of it nor the space it leaves
+            // can be used again
+            if !front_first {
+                passed.extend(spans.split_off(extent.start));
             }
-            let last_file = self
-                .files
-                .pop_back()
-                .ok_or(anyhow::anyhow!("No files left"))?;
-            if last_file.size > next_empty_space {
-                self.files
-                    .push_front(File::new(last_file.id, next_empty_space));
-                self.files
-                    .push_back(File::new(last_file.id, last_file.size - next_empty_space));
-                self.empty_space.push_back(next_empty_space);
-                self.empty_space.push_front(0)
+            let Some(space) = spans.find(fit, extent.len, extent.start) else {
+                continue;
+            };
+            spans.remove(space);
+            spans.insert(Extent {
+                start: space.start + extent.len,
+                len: space.len - extent.len,
+            });
+            if front_first {
+                spans.insert(*extent);
             } else {
-                let size = last_file.size;
-                self.files.push_front(last_file);
-                self.empty_space.push_back(size);
-                self.empty_space.push_front(next_empty_space - size);
+                passed.push(*extent);
+            }
+            extent.start = space.start;
+            moves.add(extent.len);
+        }
+        self.free = spans.into_extents();
+        self.free.extend(passed);
+        self.coalesce_free();
+        moves
+    }
+
+    // Slides every file left up against the one before it
+    fn compact_in_order(&mut self) -> Moves {
+        let mut moves = Moves::default();
+        let mut end = 0;
+        for file in &mut self.files {
+            let extent = &mut file.extents[0];
+            if extent.len == 0 {
+                continue;
+            }
+            if extent.start > end {
+                extent.start = end;
+                moves.add(extent.len);
+            }
+            end = extent.end();
+        }
+        self.free = vec![Extent {
+            start: end,
+            len: self.len - end,
+        }];
+        self.coalesce_free();
+        moves
+    }
+
+    // Sorts the free extents and merges any that touch
+    fn coalesce_free(&mut self) {
+        self.free.retain(|space| space.len > 0);
+        self.free.sort_by_key(|space| space.start);
+        let mut merged: Vec<Extent> = Vec::with_capacity(self.free.len());
+        for space in self.free.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.end() == space.start => last.len += space.len,
+                _ => merged.push(space),
             }
         }
-        println!("{}", final_segments);
+        self.free = merged;
+    }
+
+    fn checksum(&self) -> anyhow::Result<u128> {
+        self.files
+            .iter()
+            .try_fold(0u128, |sum, file| sum.checked_add(file.checksum()?))
+            .ok_or_else(|| anyhow!("Checksum overflows 128 bits"))
+    }
+
+    fn largest_free(&self) -> usize {
+        self.free.iter().map(|space| space.len).max().unwrap_or(0)
+    }
+
+    // One digit per block while IDs fit in one, otherwise a column per block
+    // wide enough for the largest ID
+    fn render(&self) -> anyhow::Result<String> {
+        if self.len > MAX_RENDERED_BLOCKS {
+            return Err(anyhow!(
+                "Disk of {} blocks is too long to render, at most {} are",
+                self.len,
+                MAX_RENDERED_BLOCKS
+            ));
+        }
+        let width = self.files.len().saturating_sub(1).to_string().len();
+        let separator = if width == 1 { "" } else { " " };
+        Ok(self
+            .blocks()
+            .into_iter()
+            .map(|block| match block {
+                Some(id) => format!("{:>width$}", id),
+                None => format!("{:>width$}", "."),
+            })
+            .collect::<Vec<_>>()
+            .join(separator))
+    }
+}
+
+// One digit per length by default, or lengths of any size when separated
+// by commas or whitespace, e.g. `12,0,3`. Without a separator there is no
+// telling where a length ends, so a lone `12` is still a file of one block
+// followed by two free ones.
+fn parse_lengths(s: &str) -> anyhow::Result<Vec<usize>> {
+    let s = s.trim();
+    let separator = |c: char| c == ',' || c.is_whitespace();
+    if s.contains(separator) {
+        s.split(separator)
+            .filter(|len| !len.is_empty())
+            .map(|len| len.parse().map_err(|_| anyhow!("Invalid length {:?}", len)))
+            .collect()
+    } else {
+        s.chars()
+            .enumerate()
+            .map(|(i, c)| {
+                c.to_digit(10).map(|len| len as usize).ok_or(anyhow!(
+                    "Invalid character {:?} at position {}",
+                    c,
+                    i
+                ))
+            })
+            .collect()
+    }
+}
 
-        Ok(checksum)
+fn solve(input: &Path, policy: Policy, render: bool) -> anyhow::Result<String> {
+    let lengths = parse_lengths(&read_to_string(input)?)?;
+    let mut disk = DiskMap::new(&lengths)?;
+    policy.compact(&mut disk);
+
+    let checksum = disk.checksum()?;
+    if render {
+        Ok(format!("{}\n{}", disk.render()?, checksum))
+    } else {
+        Ok(checksum.to_string())
     }
 }
 
-pub fn part_a(input: &Path) -> anyhow::Result<usize> {
-    let s = read_to_string(input)?;
-    let mut segments = Segments::new(s);
-    segments.compute_checksum()
+pub fn part_a(input: &Path, render: bool) -> anyhow::Result<String> {
+    solve(input, Policy::Blocks, render)
+}
+
+pub fn part_b(input: &Path, render: bool) -> anyhow::Result<String> {
+    solve(input, Policy::FirstFit, render)
+}
+
+pub fn policies(input: &Path) -> anyhow::Result<String> {
+    let lengths = parse_lengths(&read_to_string(input)?)?;
+    let mut out = String::from("policy,checksum,moves,bytes_moved,free_runs,largest_free\n");
+    for policy in POLICIES {
+        let mut disk = DiskMap::new(&lengths)?;
+        let moves = policy.compact(&mut disk);
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            policy,
+            disk.checksum()?,
+            moves.count,
+            moves.bytes,
+            disk.free.len(),
+            disk.largest_free(),
+        ));
+    }
+    Ok(out)
+}
+
+// Tiles the input's file and free pairs up to each length and times every
+// policy, so the time per digit should stay flat as the map grows.
+pub fn bench(input: &Path) -> anyhow::Result<String> {
+    let mut tile = parse_lengths(&read_to_string(input)?)?;
+    if tile.is_empty() {
+        return Err(anyhow!("Empty disk map"));
+    }
+    if tile.len() % 2 == 1 {
+        tile.push(0);
+    }
+    let mut out = String::from("digits,blocks,policy,ms,ns_per_digit\n");
+    for digits in [10_000, 100_000, 1_000_000] {
+        let lengths: Vec<usize> = tile.iter().copied().cycle().take(digits).collect();
+        for policy in POLICIES {
+            let mut disk = DiskMap::new(&lengths)?;
+            let start = Instant::now();
+            policy.compact(&mut disk);
+            disk.checksum()?;
+            let elapsed = start.elapsed();
+            out.push_str(&format!(
+                "{},{},{},{:.1},{:.1}\n",
+                digits,
+                disk.len,
+                policy,
+                elapsed.as_secs_f64() * 1e3,
+                elapsed.as_nanos() as f64 / digits as f64,
+            ));
+        }
+    }
+    Ok(out)
 }
@@ -23,7 +23,6 @@ mod day6;
 mod day7;
 mod day8;
 mod day9;
-mod day9b;
 
 use clap::{Parser, ValueEnum};
 
@@ -33,20 +32,37 @@ enum Problem {
     Day1B,
     Day2A,
     Day2B,
+    Day2Explain,
     Day3A,
     Day3B,
+    Day3Summary,
+    Day3Trace,
+    Day3Recover,
     Day4A,
     Day4B,
+    Day4Search,
+    Day4Bench,
+    Day4Template,
+    Day4Generate,
     Day5A,
     Day5B,
+    Day5Explain,
+    Day5Orderings,
     Day6A,
     Day6B,
+    Day6Coverage,
+    Day6Loops,
     Day7A,
     Day7B,
+    Day7Expressions,
+    Day7Semantics,
     Day8A,
     Day8B,
+    Day8Ratios,
     Day9A,
     Day9B,
+    Day9Bench,
+    Day9Policies,
     Day10A,
     Day10B,
     Day11A,
@@ -87,10 +103,95 @@ struct Cli {
     problem: Problem,
 
     input: std::path::PathBuf,
+
+    /// Most levels the day 2 b dampener may remove from a report
+    #[arg(long, default_value_t = 1)]
+    max_removals: usize,
+
+    /// Smallest step between adjacent day 2 levels
+    #[arg(long, default_value_t = 1)]
+    min_step: i8,
+
+    /// Largest step between adjacent day 2 levels
+    #[arg(long, default_value_t = 3)]
+    max_diff: i8,
+
+    /// Day 3 instruction set, e.g. `mul,add:3,sub:2:4:16`
+    #[arg(long, default_value = "mul")]
+    instructions: day3::InstructionSet,
+
+    /// Maximum edit distance for recovered day 3 instructions
+    #[arg(long, default_value_t = 1)]
+    max_edits: usize,
+
+    /// Word list for the day 4 word search
+    #[arg(long)]
+    words: Option<std::path::PathBuf>,
+
+    /// Day 4 template with `/` between rows and `.` as a wildcard
+    #[arg(long, default_value = "M.S/.A./M.S")]
+    template: String,
+
+    /// Which orientations of the day 4 template to match
+    #[arg(long, value_enum, default_value_t = day4::Symmetry::All)]
+    symmetry: day4::Symmetry,
+
+    /// Width of the generated day 4 puzzle
+    #[arg(long, default_value_t = 15)]
+    width: usize,
+
+    /// Height of the generated day 4 puzzle
+    #[arg(long, default_value_t = 15)]
+    height: usize,
+
+    /// Random seed for generated puzzles
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Render day 6 output as grids instead of coordinates, and print the
+    /// compacted day 9 disk layout
+    #[arg(long)]
+    render: bool,
+
+    /// Which way the day 6 guard turns at an obstacle
+    #[arg(long, value_enum, default_value_t = day6::Turn::Right)]
+    turn: day6::Turn,
+
+    /// Glyphs that block the day 6 guard
+    #[arg(long, default_value = "#")]
+    obstacles: String,
+
+    /// Let day 6 guards wrap around the map's edges instead of leaving
+    #[arg(long)]
+    wrap: bool,
+
+    /// Day 7 operators, any of `+ * || - / ^ xor` separated by commas
+    #[arg(long, default_value = "+,*,||", allow_hyphen_values = true)]
+    ops: day7::OpSet,
+
+    /// Also search every bracketing of the day 7 equations, which is slow
+    /// on long equations
+    #[arg(long)]
+    parenthesize: bool,
+
+    /// Solve day 7 a, b and expressions with arbitrary-precision integers,
+    /// for calibrations that overflow 64 bits
+    #[arg(long)]
+    big: bool,
+
+    /// Day 8 distance ratios between an antinode's two antennas, e.g. `2,3/2`
+    #[arg(long, default_value = "2")]
+    ratios: day8::Ratios,
+
+    /// Read day 8 antennas as `a 3,4,7` coordinate lines within this box,
+    /// one size or `min..max` range per axis
+    #[arg(long, allow_hyphen_values = true)]
+    bounds: Option<day8::Bounds>,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
+    let rules = day6::Rules::new(args.turn, &args.obstacles, args.wrap);
     match args.problem {
         Problem::Day1A => {
             println!("{}", day1::part_a(&args.input)?);
@@ -99,16 +200,40 @@ fn main() -> anyhow::Result<()> {
             println!("{}", day1::part_b(&args.input)?);
         }
         Problem::Day2A => {
-            println!("{}", day2::part_a(&args.input)?);
+            println!(
+                "{}",
+                day2::part_a(&args.input, args.min_step, args.max_diff)?
+            );
         }
         Problem::Day2B => {
-            println!("{}", day2::part_b(&args.input)?);
+            println!(
+                "{}",
+                day2::part_b(&args.input, args.max_removals, args.min_step, args.max_diff)?
+            );
+        }
+        Problem::Day2Explain => {
+            print!(
+                "{}",
+                day2::explain(&args.input, args.max_removals, args.min_step, args.max_diff)?
+            );
         }
         Problem::Day3A => {
-            println!("{:?}", day3::part_a(&args.input)?);
+            println!("{:?}", day3::part_a(&args.input, &args.instructions)?);
         }
         Problem::Day3B => {
-            println!("{:?}", day3::part_b(&args.input)?);
+            println!("{:?}", day3::part_b(&args.input, &args.instructions)?);
+        }
+        Problem::Day3Summary => {
+            println!("{}", day3::summary(&args.input, &args.instructions)?);
+        }
+        Problem::Day3Trace => {
+            print!("{}", day3::trace(&args.input, &args.instructions)?);
+        }
+        Problem::Day3Recover => {
+            print!(
+                "{}",
+                day3::recover(&args.input, &args.instructions, args.max_edits)?
+            );
         }
         Problem::Day4A => {
             println!("{:?}", day4::part_a(&args.input)?);
@@ -116,35 +241,87 @@ fn main() -> anyhow::Result<()> {
         Problem::Day4B => {
             println!("{:?}", day4::part_b(&args.input)?);
         }
+        Problem::Day4Search => {
+            let words = args.words.ok_or(anyhow::anyhow!("--words is required"))?;
+            print!("{}", day4::search(&args.input, &words)?);
+        }
+        Problem::Day4Bench => {
+            print!("{}", day4::bench(&args.input)?);
+        }
+        Problem::Day4Template => {
+            print!(
+                "{}",
+                day4::template(&args.input, &args.template, args.symmetry)?
+            );
+        }
+        Problem::Day4Generate => {
+            print!(
+                "{}",
+                day4::generate(&args.input, args.width, args.height, args.seed)?
+            );
+        }
         Problem::Day5A => {
             println!("{:?}", day5::part_a(&args.input)?);
         }
         Problem::Day5B => {
             println!("{:?}", day5::part_b(&args.input)?);
         }
+        Problem::Day5Explain => {
+            print!("{}", day5::explain(&args.input)?);
+        }
+        Problem::Day5Orderings => {
+            print!("{}", day5::orderings(&args.input)?);
+        }
         Problem::Day6A => {
-            println!("{:?}", day6::part_a(&args.input)?);
+            println!("{:?}", day6::part_a(&args.input, &rules)?);
         }
         Problem::Day6B => {
-            println!("{:?}", day6::part_b(&args.input)?);
+            println!("{:?}", day6::part_b(&args.input, &rules)?);
+        }
+        Problem::Day6Coverage => {
+            print!("{}", day6::coverage(&args.input, &rules, args.render)?);
+        }
+        Problem::Day6Loops => {
+            print!("{}", day6::loops(&args.input, &rules, args.render)?);
         }
         Problem::Day7A => {
-            println!("{:?}", day7::part_a(&args.input)?);
+            println!("{}", day7::part_a(&args.input, args.big)?);
         }
         Problem::Day7B => {
-            println!("{:?}", day7::part_b(&args.input)?);
+            println!("{}", day7::part_b(&args.input, args.big)?);
+        }
+        Problem::Day7Expressions => {
+            print!("{}", day7::expressions(&args.input, &args.ops, args.big)?);
+        }
+        Problem::Day7Semantics => {
+            print!(
+                "{}",
+                day7::semantics(&args.input, &args.ops, args.parenthesize)?
+            );
         }
         Problem::Day8A => {
-            println!("{:?}", day8::part_a(&args.input)?);
+            println!("{:?}", day8::part_a(&args.input, args.bounds.as_ref())?);
         }
         Problem::Day8B => {
-            println!("{:?}", day8::part_b(&args.input)?);
+            println!("{:?}", day8::part_b(&args.input, args.bounds.as_ref())?);
+        }
+        Problem::Day8Ratios => {
+            println!(
+                "{:?}",
+                day8::ratios(&args.input, &args.ratios, args.bounds.as_ref())?
+            );
         }
         Problem::Day9A => {
-            println!("{:?}", day9::part_a(&args.input)?);
+            println!("{}", day9::part_a(&args.input, args.render)?);
         }
         Problem::Day9B => {
-            println!("{:?}", day9b::part_b(&args.input)?);
+            println!("{}", day9::part_b(&args.input, args.render)?);
+        }
+        Problem::Day9Bench => {
+            print!("{}", day9::bench(&args.input)?);
+        }
+        Problem::Day9Policies => {
+            print!("{}", day9::policies(&args.input)?);
         }
         Problem::Day10A => {
             println!("{:?}", day10::part_a(&args.input)?);